@@ -1,56 +1,141 @@
-use crate::sha128::{OVERFLOW_PROTECTION, Sha128};
-use crate::error::{AccountDNE, TransactionError};
+use crate::hash::Hash256;
+use crate::error::{AccountDNE, DecodeError, InvalidBlock, TransactionError};
+use crate::keys::{Keypair, PublicKey, Signature, SIGNATURE_LENGTH};
+use crate::encoding::{Decode, Encode, Reader, ENCODING_VERSION};
+use crate::state::{StateProof, StateTree};
+use crate::merkle::MerkleTree;
 use std::collections::HashMap;
 use std::fmt;
 
+const COINBASE_TAG: u8 = 0;
+const TRANSFER_TAG: u8 = 1;
+
 #[derive(Default)]
 pub struct Accounts {
-    accounts_map: HashMap<u128, Account>
+    accounts_map: HashMap<u128, Account>,
+    /// Confirmed balances and nonces, updated for the accounts every applied or undone block touches.
+    state: StateTree,
 }
 
 pub struct Account {
     #[allow(dead_code)]
-    public_key: Option<u128>,
+    public_key: Option<PublicKey>,
     amount: u128,
     pending_amount: u128,
+    nonce: u64,
+    pending_nonce: u64,
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Transaction {
-    hash: u128,
-    sender_address: u128,
+    hash: Hash256,
+    kind: TransactionKind,
     receiver_address: u128,
     amount: u128,
 }
 
+#[derive(PartialEq, Clone, Debug)]
+enum TransactionKind {
+    /// Creates new coins for the miner, only valid as the first transaction of a block.
+    Coinbase { height: u64 },
+    Transfer(Transfer),
+}
+
+/// What applying a block changed in `Accounts`, enough to revert it with `Accounts::undo_block`.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct BlockUndo {
+    /// Every account the block touched, in the order they were first touched.
+    changes: Vec<AccountUndo>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct AccountUndo {
+    address: u128,
+    /// Balance and nonce before the block, `None` if the block created the account.
+    prior: Option<(u128, u64)>,
+}
+
+/// The part of a transaction that spends from the sender's account.
+#[derive(PartialEq, Clone, Debug)]
+struct Transfer {
+    sender_public_key: PublicKey,
+    nonce: u64,
+    fee: u128,
+    /// Last block height the transaction can be mined at.
+    expiry: Option<u64>,
+    signature: Signature,
+}
+
 impl Accounts {
     pub fn new() -> Self {
-        let mut accounts_map = HashMap::new();
-        let mut system_account = Account::new_with_private_key(31);
-        system_account.amount = 21_000_000;
-        accounts_map.insert(Sha128::address(31), system_account);
-        Accounts {
-            accounts_map,
-        }
+        Accounts::default()
     }
 
-    pub(super) fn update_accounts(&mut self, transactions: Vec<Transaction>) {
+    /// Applies the transactions of a block to the confirmed balances and returns the undo data of the block.
+    ///
+    /// Every transaction is first checked against the balances and nonces left by the transactions before it, with checked arithmetic.
+    /// Only once the whole block is known to apply are the changes committed, so on error the accounts are left untouched.
+    /// Pending amounts are left to the mempool.
+    pub(crate) fn apply_block(&mut self, transactions: &[Transaction]) -> Result<BlockUndo, InvalidBlock> {
+        // balance and nonce of every touched account after the transactions checked so far
+        let mut staged: HashMap<u128, (u128, u64)> = HashMap::new();
+        let mut undo = BlockUndo::default();
+        let confirmed = |address: &u128| self.accounts_map.get(address).map(|account| (account.amount, account.nonce));
+
         for transaction in transactions {
-            let sender_address = transaction.get_sender_address();
-            if let Some(sender_account) = self.accounts_map.get_mut(&sender_address) {
-                sender_account.amount -= transaction.amount;
-                sender_account.pending_amount -= transaction.amount;
-            };
-
-            let receiver_address = transaction.get_receiver_address();
-            if let Some(receiver_account) = self.accounts_map.get_mut(&receiver_address) {
-                receiver_account.amount += transaction.amount;
-            } else {
-                let mut new_account = Account::new();
-                new_account.amount += transaction.amount;
-                self.accounts_map.insert(receiver_address, new_account);
+            if let TransactionKind::Transfer(transfer) = &transaction.kind {
+                let sender_address = transfer.sender_public_key.address();
+                let (amount, nonce) = staged.get(&sender_address).copied().or_else(|| confirmed(&sender_address))
+                    .ok_or(InvalidBlock::UnknownSender(transaction.hash))?;
+                if transfer.nonce != nonce {
+                    return Err(InvalidBlock::BadNonce { transaction: transaction.hash, expected: nonce, found: transfer.nonce });
+                }
+                let amount = transaction.amount.checked_add(transfer.fee)
+                    .and_then(|total| amount.checked_sub(total))
+                    .ok_or(InvalidBlock::InsufficientBalance(transaction.hash))?;
+                undo.record(sender_address, self.accounts_map.get(&sender_address));
+                staged.insert(sender_address, (amount, nonce + 1));
+            }
+
+            let receiver_address = transaction.receiver_address;
+            let (amount, nonce) = staged.get(&receiver_address).copied().or_else(|| confirmed(&receiver_address)).unwrap_or((0, 0));
+            let amount = amount.checked_add(transaction.amount)
+                .ok_or(InvalidBlock::BalanceOverflow(transaction.hash))?;
+            undo.record(receiver_address, self.accounts_map.get(&receiver_address));
+            staged.insert(receiver_address, (amount, nonce));
+        }
+
+        for (address, (amount, nonce)) in staged {
+            let account = self.accounts_map.entry(address).or_insert_with(Account::new);
+            account.amount = amount;
+            account.nonce = nonce;
+            account.pending_nonce = account.pending_nonce.max(nonce);
+            self.state.update(address, Some((amount, nonce)));
+        }
+        for transfer in transactions.iter().filter_map(Transaction::get_transfer) {
+            if let Some(account) = self.accounts_map.get_mut(&transfer.sender_public_key.address()) {
+                account.public_key = Some(transfer.sender_public_key);
             }
         }
+        Ok(undo)
+    }
+
+    /// Reverts a block applied with `apply_block`, restoring the balances and nonces it changed and removing the accounts it created.
+    /// Pending amounts are left untouched, callers readmit their pending transactions afterwards.
+    pub(crate) fn undo_block(&mut self, undo: &BlockUndo) {
+        for change in undo.changes.iter().rev() {
+            match change.prior {
+                Some((amount, nonce)) => {
+                    let account = self.accounts_map.entry(change.address).or_insert_with(Account::new);
+                    account.amount = amount;
+                    account.nonce = nonce;
+                },
+                None => {
+                    self.accounts_map.remove(&change.address);
+                },
+            }
+            self.state.update(change.address, change.prior);
+        }
     }
 
     pub fn get_account_amount(&self, address: &u128) -> Result<u128, AccountDNE> {
@@ -60,16 +145,77 @@ impl Accounts {
         }
     }
 
-    pub fn sign_transaction(&mut self, private_key: u128, receiver_address: u128, amount: u128) -> Result<Transaction, TransactionError> {
-        let sender_address = Sha128::address(private_key);
-        match self.accounts_map.get_mut(&sender_address) {
+    pub fn get_account_nonce(&self, address: &u128) -> Result<u64, AccountDNE> {
+        match &self.accounts_map.get(address) {
+            Some(account) => Ok(account.nonce),
+            None => Err(AccountDNE)
+        }
+    }
+
+    /// Root of the state tree over the confirmed balances and nonces, committed to by the block that produced them.
+    pub fn state_root(&self) -> Hash256 {
+        self.state.root()
+    }
+
+    /// Proof of the confirmed balance and nonce of `address` against `state_root`, or that the account does not exist.
+    pub fn prove_account(&self, address: &u128) -> StateProof {
+        self.state.prove(*address)
+    }
+
+    /// Signs a transaction with the nonce following the sender's last pending transaction.
+    pub fn sign_transaction(&self, keypair: &Keypair, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) -> Transaction {
+        let nonce = self.accounts_map.get(&keypair.address()).map_or(0, |account| account.pending_nonce);
+        Transaction::generate_transaction(keypair, receiver_address, amount, fee, nonce, expiry)
+    }
+
+    /// Verifies the signature and nonce of an already signed transaction and reserves its amount and fee from the sender's balance.
+    pub fn admit_transaction(&mut self, transaction: &Transaction) -> Result<(), TransactionError> {
+        self.check_transaction(transaction)?;
+        self.reserve_transaction(transaction);
+        Ok(())
+    }
+
+    /// Checks that a transaction could be admitted without reserving anything.
+    /// The nonce has to follow the sender's last pending transaction.
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        self.check_spend(transaction, None)
+    }
+
+    /// Checks that a transaction can take the place of the pending transaction `replaced` from the same sender with the same nonce.
+    /// The amount and fee reserved for `replaced` count towards the sender's balance.
+    pub fn check_replacement(&self, transaction: &Transaction, replaced: &Transaction) -> Result<(), TransactionError> {
+        if transaction.get_sender_address() != replaced.get_sender_address() || transaction.get_nonce() != replaced.get_nonce() {
+            return Err(TransactionError("Replacement has a different sender or nonce!".to_string()));
+        }
+        self.check_spend(transaction, Some(replaced))
+    }
+
+    fn check_spend(&self, transaction: &Transaction, replaced: Option<&Transaction>) -> Result<(), TransactionError> {
+        let transfer = transaction.get_transfer()
+            .ok_or(TransactionError("Coinbase transactions are only created by miners!".to_string()))?;
+        transaction.verify_signature()?;
+
+        let amount = transaction.amount.checked_add(transfer.fee)
+            .ok_or(TransactionError("Amount and fee overflow!".to_string()))?;
+        match self.accounts_map.get(&transfer.sender_public_key.address()) {
             Some(account) => {
-                if account.get_amount() - account.get_pending_amount() >= amount {
-                    account.pending_amount += amount;
-                    Ok(Transaction::generate_transaction(private_key, receiver_address, amount))
+                let (expected_nonce, pending_amount) = match replaced {
+                    Some(replaced) => (transfer.nonce, account.get_pending_amount().saturating_sub(replaced.get_total())),
+                    None => (account.pending_nonce, account.get_pending_amount()),
+                };
+                if transfer.nonce < expected_nonce {
+                    let err_str = format!("Nonce too low! expected: `{}`, got: `{}`", expected_nonce, transfer.nonce);
+                    Err(TransactionError(err_str))
+                }
+                else if transfer.nonce > expected_nonce {
+                    let err_str = format!("Nonce too high! expected: `{}`, got: `{}`", expected_nonce, transfer.nonce);
+                    Err(TransactionError(err_str))
+                }
+                else if account.get_amount().saturating_sub(pending_amount) >= amount {
+                    Ok(())
                 }
                 else {
-                    let err_str = format!("Insufficient amount! amount: `{}`, pending amount: `{}`", account.get_amount(), account.get_pending_amount());
+                    let err_str = format!("Insufficient amount! amount: `{}`, pending amount: `{}`", account.get_amount(), pending_amount);
                     Err(TransactionError(err_str))
                 }
             },
@@ -77,6 +223,46 @@ impl Accounts {
         }
     }
 
+    /// Reserves the amount and fee of a checked transaction from the sender's balance.
+    pub(crate) fn reserve_transaction(&mut self, transaction: &Transaction) {
+        if let Some(account) = transaction.get_sender_address().and_then(|address| self.accounts_map.get_mut(&address)) {
+            account.pending_amount = account.pending_amount.saturating_add(transaction.get_total());
+            account.pending_nonce += 1;
+        }
+    }
+
+    /// Gives back the amount and fee reserved by a pending transaction that is dropped without being mined.
+    /// Only the sender's last pending transaction can be released, otherwise its nonce would leave a gap.
+    pub(crate) fn release_transaction(&mut self, transaction: &Transaction) {
+        if let Some(account) = transaction.get_sender_address().and_then(|address| self.accounts_map.get_mut(&address)) {
+            account.pending_amount = account.pending_amount.saturating_sub(transaction.get_total());
+            account.pending_nonce = account.pending_nonce.min(transaction.get_nonce().unwrap_or(0)).max(account.nonce);
+        }
+    }
+
+    /// Moves the reservation of a replaced pending transaction over to its checked replacement.
+    pub(crate) fn replace_reservation(&mut self, replaced: &Transaction, transaction: &Transaction) {
+        if let Some(account) = transaction.get_sender_address().and_then(|address| self.accounts_map.get_mut(&address)) {
+            account.pending_amount = account.pending_amount.saturating_sub(replaced.get_total()).saturating_add(transaction.get_total());
+        }
+    }
+
+    /// Forgets the reservations of every account, used before the mempool is readmitted after blocks are disconnected.
+    pub(crate) fn clear_all_pending(&mut self) {
+        for account in self.accounts_map.values_mut() {
+            account.pending_amount = 0;
+            account.pending_nonce = account.nonce;
+        }
+    }
+
+    /// Forgets every reservation of an account, making its pending state match its confirmed state.
+    pub(crate) fn clear_pending(&mut self, address: u128) {
+        if let Some(account) = self.accounts_map.get_mut(&address) {
+            account.pending_amount = 0;
+            account.pending_nonce = account.nonce;
+        }
+    }
+
     pub fn print_accounts(&self) {
         for (account_address, account) in &self.accounts_map {
             println!("account address: {}, account balance: {}", account_address, account.get_amount())
@@ -90,14 +276,8 @@ impl Account {
             public_key: None,
             amount: 0,
             pending_amount: 0,
-        }
-    }
-
-    fn new_with_private_key(private_key: u128) -> Self {
-        Account {
-            public_key: Some(Sha128::public_key(private_key)),
-            amount: 0,
-            pending_amount: 0,
+            nonce: 0,
+            pending_nonce: 0,
         }
     }
 
@@ -110,73 +290,281 @@ impl Account {
     }
 }
 
+impl BlockUndo {
+    /// Remembers the state of an account before the block first touches it.
+    fn record(&mut self, address: u128, account: Option<&Account>) {
+        if self.changes.iter().all(|change| change.address != address) {
+            let prior = account.map(|account| (account.amount, account.nonce));
+            self.changes.push(AccountUndo { address, prior });
+        }
+    }
+}
+
+impl Encode for BlockUndo {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.changes.len() as u32).to_be_bytes());
+        for change in &self.changes {
+            bytes.extend_from_slice(&change.address.to_be_bytes());
+            match change.prior {
+                Some((amount, nonce)) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&amount.to_be_bytes());
+                    bytes.extend_from_slice(&nonce.to_be_bytes());
+                },
+                None => bytes.push(0),
+            }
+        }
+    }
+}
+
+impl Decode for BlockUndo {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let change_count = reader.read_length(16 + 1)?;
+        let changes = (0..change_count)
+            .map(|_| {
+                let address = reader.read_u128()?;
+                let prior = match reader.read_u8()? {
+                    0 => None,
+                    1 => Some((reader.read_u128()?, reader.read_u64()?)),
+                    _ => return Err(DecodeError::InvalidField("account undo")),
+                };
+                Ok(AccountUndo { address, prior })
+            })
+            .collect::<Result<Vec<AccountUndo>, DecodeError>>()?;
+        Ok(BlockUndo { changes })
+    }
+}
+
 impl Transaction {
-    fn generate_transaction(private_key: u128, receiver_address: u128, amount: u128) -> Self {
-        Transaction {
-            hash: Sha128::transaction_hash(Sha128::public_key(private_key), receiver_address, amount),
-            sender_address: Sha128::address(private_key),
+    /// Length of the shortest encoded transaction, used to bound the transaction count of an encoded block.
+    pub(crate) const MIN_ENCODED_LENGTH: usize = 1 + 8 + 16 + 16;
+
+    pub(crate) fn generate_transaction(keypair: &Keypair, receiver_address: u128, amount: u128, fee: u128, nonce: u64, expiry: Option<u64>) -> Self {
+        let mut transaction = Transaction {
+            hash: Hash256::ZERO,
+            kind: TransactionKind::Transfer(Transfer {
+                sender_public_key: keypair.public_key(),
+                nonce,
+                fee,
+                expiry,
+                signature: Signature::from_bytes([0; SIGNATURE_LENGTH]),
+            }),
             receiver_address,
             amount,
+        };
+        let signature = keypair.sign(&transaction.signing_bytes());
+        if let TransactionKind::Transfer(transfer) = &mut transaction.kind {
+            transfer.signature = signature;
         }
+        transaction.hash = Hash256::transaction_hash(&transaction);
+        transaction
     }
 
-    pub fn transaction_to_miner(receiver_address: u128, amount: u8) -> Self {
-        let amount = amount as u128;
-        let system_private_key = 31;
-        let miner_reward_transaction = Transaction {
-            hash: Sha128::transaction_hash(Sha128::address(system_private_key), receiver_address, amount),
-            sender_address: Sha128::address(system_private_key),
+    /// Creates `amount` new coins for the miner of the block at `height`. The height makes every coinbase unique.
+    pub fn coinbase(receiver_address: u128, amount: u128, height: u64) -> Self {
+        let mut transaction = Transaction {
+            hash: Hash256::ZERO,
+            kind: TransactionKind::Coinbase { height },
             receiver_address,
             amount,
         };
+        transaction.hash = Hash256::transaction_hash(&transaction);
+        transaction
+    }
+
+    /// The bytes covered by the sender's signature, which is the encoded transaction without the signature.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![ENCODING_VERSION];
+        self.encode_unsigned(&mut bytes);
+        bytes
+    }
 
-        println!("Miner reward transaction\n{}\n", miner_reward_transaction);
+    fn encode_unsigned(&self, bytes: &mut Vec<u8>) {
+        match &self.kind {
+            TransactionKind::Coinbase { height } => {
+                bytes.push(COINBASE_TAG);
+                bytes.extend_from_slice(&height.to_be_bytes());
+                bytes.extend_from_slice(&self.receiver_address.to_be_bytes());
+                bytes.extend_from_slice(&self.amount.to_be_bytes());
+            },
+            TransactionKind::Transfer(transfer) => {
+                bytes.push(TRANSFER_TAG);
+                bytes.extend_from_slice(transfer.sender_public_key.as_bytes());
+                bytes.extend_from_slice(&transfer.nonce.to_be_bytes());
+                bytes.extend_from_slice(&self.receiver_address.to_be_bytes());
+                bytes.extend_from_slice(&self.amount.to_be_bytes());
+                bytes.extend_from_slice(&transfer.fee.to_be_bytes());
+                match transfer.expiry {
+                    Some(expiry) => {
+                        bytes.push(1);
+                        bytes.extend_from_slice(&expiry.to_be_bytes());
+                    },
+                    None => bytes.push(0),
+                }
+            },
+        }
+    }
+
+    /// Checks that the transaction hash matches its contents and that the sender signed it.
+    pub fn validate(&self) -> Result<(), InvalidBlock> {
+        if self.hash != Hash256::transaction_hash(self) {
+            return Err(InvalidBlock::BadTransactionHash(self.hash));
+        }
+        self.verify_signature().map_err(|_| InvalidBlock::BadSignature(self.hash))
+    }
+
+    /// Coinbase transactions have no sender and so nothing to verify.
+    pub fn verify_signature(&self) -> Result<(), TransactionError> {
+        match &self.kind {
+            TransactionKind::Coinbase { .. } => Ok(()),
+            TransactionKind::Transfer(transfer) => transfer.sender_public_key.verify(&self.signing_bytes(), &transfer.signature),
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        matches!(self.kind, TransactionKind::Coinbase { .. })
+    }
+
+    /// Height of the block a coinbase transaction belongs to, `None` for transfers.
+    pub fn get_coinbase_height(&self) -> Option<u64> {
+        match self.kind {
+            TransactionKind::Coinbase { height } => Some(height),
+            TransactionKind::Transfer(_) => None,
+        }
+    }
 
-        miner_reward_transaction
+    pub fn get_sender_public_key(&self) -> Option<&PublicKey> {
+        self.get_transfer().map(|transfer| &transfer.sender_public_key)
     }
 
-    pub(crate) fn get_sender_address(&self) -> u128 {
-        self.sender_address
+    pub(crate) fn get_sender_address(&self) -> Option<u128> {
+        self.get_transfer().map(|transfer| transfer.sender_public_key.address())
     }
 
-    pub(crate) fn get_receiver_address(&self) -> u128 {
+    pub fn get_receiver_address(&self) -> u128 {
         self.receiver_address
     }
 
-    pub fn get_hash(&self) -> u128 {
-        self.hash
+    pub fn get_amount(&self) -> u128 {
+        self.amount
     }
 
-    pub(crate) fn get_merkle(curr_trans: &[Transaction]) -> u128 {
-        let mut merkle = curr_trans.iter().map(|t| t.get_hash()).collect::<Vec<u128>>();
+    pub fn get_fee(&self) -> u128 {
+        self.get_transfer().map_or(0, |transfer| transfer.fee)
+    }
+
+    /// The amount and fee together, which is what the sender pays.
+    pub(crate) fn get_total(&self) -> u128 {
+        self.amount.saturating_add(self.get_fee())
+    }
+
+    pub fn get_nonce(&self) -> Option<u64> {
+        self.get_transfer().map(|transfer| transfer.nonce)
+    }
+
+    pub fn get_expiry(&self) -> Option<u64> {
+        self.get_transfer().and_then(|transfer| transfer.expiry)
+    }
 
-        if merkle.len() % 2 == 1 {
-            merkle.push(merkle.last().cloned().unwrap());
+    /// Whether the transaction can no longer be mined in the block at `height`.
+    pub fn is_expired_at(&self, height: u64) -> bool {
+        self.get_expiry().is_some_and(|expiry| expiry < height)
+    }
+
+    pub fn get_hash(&self) -> Hash256 {
+        self.hash
+    }
+
+    fn get_transfer(&self) -> Option<&Transfer> {
+        match &self.kind {
+            TransactionKind::Coinbase { .. } => None,
+            TransactionKind::Transfer(transfer) => Some(transfer),
         }
+    }
 
-        while merkle.len() > 1 {
-            let mut h1 = merkle.remove(0) % OVERFLOW_PROTECTION;
-            let h2 = merkle.remove(0) % OVERFLOW_PROTECTION;
-            h1 += h2;
-            let nh = Sha128::hash(h1);
-            merkle.push(nh);
+    pub(crate) fn get_merkle_tree(transactions: &[Transaction]) -> MerkleTree {
+        MerkleTree::new(transactions.iter().map(Transaction::get_hash).collect())
+    }
+}
+
+/// The transaction hash is not encoded, it is recomputed from the decoded fields.
+impl Encode for Transaction {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        self.encode_unsigned(bytes);
+        if let TransactionKind::Transfer(transfer) = &self.kind {
+            bytes.extend_from_slice(transfer.signature.as_bytes());
         }
-        merkle.pop().unwrap_or(0)
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut transaction = match reader.read_u8()? {
+            COINBASE_TAG => Transaction {
+                hash: Hash256::ZERO,
+                kind: TransactionKind::Coinbase { height: reader.read_u64()? },
+                receiver_address: reader.read_u128()?,
+                amount: reader.read_u128()?,
+            },
+            TRANSFER_TAG => {
+                let sender_public_key = PublicKey::from_bytes(reader.read_array()?);
+                let nonce = reader.read_u64()?;
+                let receiver_address = reader.read_u128()?;
+                let amount = reader.read_u128()?;
+                let fee = reader.read_u128()?;
+                let expiry = match reader.read_u8()? {
+                    0 => None,
+                    1 => Some(reader.read_u64()?),
+                    _ => return Err(DecodeError::InvalidField("transaction expiry")),
+                };
+                let signature = Signature::from_bytes(reader.read_array()?);
+                Transaction {
+                    hash: Hash256::ZERO,
+                    kind: TransactionKind::Transfer(Transfer { sender_public_key, nonce, fee, expiry, signature }),
+                    receiver_address,
+                    amount,
+                }
+            },
+            _ => return Err(DecodeError::InvalidField("transaction kind")),
+        };
+        transaction.hash = Hash256::transaction_hash(&transaction);
+        Ok(transaction)
     }
 }
 
 impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f,
+        match &self.kind {
+            TransactionKind::Coinbase { height } => write!(f,
+               "\
+               ------------\n\
+               transaction:{}\n\
+               coinbase of block <{}> -> to address <{}>\n\
+               amount:<{}>\n\
+               ------------",
+               self.hash,
+               height,
+               self.receiver_address,
+               self.amount),
+            TransactionKind::Transfer(transfer) => write!(f,
                "\
                ------------\n\
                transaction:{}\n\
                from address <{}> -> to address <{}>\n\
+               nonce:<{}>\n\
                amount:<{}>\n\
+               fee:<{}>\n\
+               expiry:<{}>\n\
+               signature:<{}>\n\
                ------------",
                self.hash,
-               self.sender_address,
+               transfer.sender_public_key.address(),
                self.receiver_address,
-               self.amount)
+               transfer.nonce,
+               self.amount,
+               transfer.fee,
+               transfer.expiry.map_or("none".to_string(), |expiry| expiry.to_string()),
+               transfer.signature),
+        }
     }
 }
@@ -1,63 +1,314 @@
 use std::fmt;
-use crate::accounts::{Accounts, Transaction};
+use crate::accounts::{Accounts, BlockUndo, Transaction};
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::error::ChainError;
-use crate::sha128::{Sha128, OVERFLOW_PROTECTION};
+use crate::error::{BlockError, ChainError, DecodeError, InvalidBlock, StorageError, TransactionError, ValidationError};
+use crate::consensus::{ConsensusParams, MAX_FUTURE_BLOCK_TIME};
+use crate::keys::Keypair;
+use crate::target::{CompactTarget, U256};
+use crate::hash::Hash256;
+use crate::encoding::{Decode, Encode, Reader};
+use crate::storage::{BlockStore, FileStore, MemoryStore};
+use crate::mempool::{Dropped, Mempool};
+use crate::fork::{BlockStatus, BlockTree, SideBlock};
+use crate::merkle::MerkleProof;
+use std::path::Path;
 
-pub struct Chain {
-    chain: Vec<Block>,
+/// Consensus version of the block header.
+/// Version 2 hashes the whole encoded header instead of a sum of its fields, version 3 uses full 256-bit hashes
+/// version 4 replaces the leading zero bits difficulty with a compact target, version 5 commits to the account state,
+/// version 6 stops pairing merkle nodes across levels and version 7 hashes merkle leaves and nodes with different tags.
+pub const BLOCK_VERSION: u32 = 7;
+
+pub struct Chain<S: BlockStore = MemoryStore> {
+    chain: S,
     accounts: Accounts,
-    curr_trans: Vec<Transaction>,
-    length: usize,
-    difficulty: u8,
-    reward: u8,
+    mempool: Mempool,
+    params: ConsensusParams,
+    /// Total work of the main chain up to every height.
+    chain_work: Vec<U256>,
+    tree: BlockTree,
 }
 
 impl Chain {
     pub fn init() -> Self {
-        let difficulty: u8 = 24;
-        let chain: Vec<Block> = vec![Block::get_genesis_block(difficulty)];
-        let accounts = Accounts::new();
-        let curr_trans: Vec<Transaction> = Vec::new();
-        let length: usize = 1;
-        let reward: u8 = 50;
+        Chain::with_store(MemoryStore::default()).expect("a new in-memory chain is always valid")
+    }
+}
+
+impl Chain<FileStore> {
+    /// Opens the chain stored in `dir`, creating it with a new genesis block if it does not exist yet.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Chain::with_store(FileStore::open(dir)?)
+    }
+}
+
+impl<S: BlockStore> Chain<S> {
+    /// Builds a chain on top of `store`, adding a genesis block if the store is empty.
+    /// Stored blocks are replayed and validated before the chain is used.
+    pub fn with_store(store: S) -> Result<Self, StorageError> {
+        Chain::with_params(store, ConsensusParams::default())
+    }
+
+    /// Same as `with_store`, with custom consensus rules.
+    pub fn with_params(mut store: S, params: ConsensusParams) -> Result<Self, StorageError> {
+        params.validate()?;
+        if store.is_empty() {
+            store.append(Block::get_genesis_block(&params))?;
+        }
 
-        Chain {
-            chain,
-            accounts,
-            curr_trans,
-            length,
-            difficulty,
-            reward,
+        let mut chain = Chain {
+            chain: store,
+            accounts: Accounts::new(),
+            mempool: Mempool::default(),
+            params,
+            chain_work: Vec::new(),
+            tree: BlockTree::default(),
+        };
+        let undo;
+        (chain.accounts, chain.chain_work, undo) = chain.replay()?;
+        for block_undo in &undo[chain.chain.undo_len()..] {
+            chain.chain.append_undo(block_undo)?;
         }
+        print_dropped(&chain.mempool.advance_to(chain.chain.len() as u64, &mut chain.accounts));
+        Ok(chain)
     }
 
-    pub fn check_health(&self, chain: &Chain) -> Result<(), ChainError> {
-        let curr_block_hash = self.chain[self.length - 1].get_hash();
-        let new_chain_prev_has = chain.chain[chain.length - 1].header.pre_hash;
+    pub fn check_health<T: BlockStore>(&self, chain: &Chain<T>) -> Result<(), ChainError> {
+        let read_error = |_| ChainError("Could not read the chain!");
+        let curr_block_hash = self.last_block().map_err(read_error)?.get_hash();
+        let new_chain_prev_has = chain.last_block().map_err(read_error)?.header.pre_hash;
 
         if curr_block_hash == new_chain_prev_has {
-            if self.chain[..self.length - 1] == chain.chain[..self.length - 2] {
-                Ok(())
-            } else {
-                Err(ChainError("Bad chain! Chain is broken!"))
+            for (block, new_chain_block) in self.chain.iter().zip(chain.chain.iter()).take(self.chain.len() - 1) {
+                if block.map_err(read_error)? != new_chain_block.map_err(read_error)? {
+                    return Err(ChainError("Bad chain! Chain is broken!"));
+                }
             }
+            Ok(())
         }
         else { Err(ChainError("Bad block! Prev has is not correct!")) }
     }
 
+    /// Replays the whole chain from genesis, re-verifying every block and the account balances it produces.
+    /// Returns the height of the first bad block and the reason it was rejected.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.replay().map(|_| ())
+    }
+
+    /// Rebuilds the account balances, the work up to every height and the undo data of every block from genesis,
+    /// validating every block on the way.
+    fn replay(&self) -> Result<(Accounts, Vec<U256>, Vec<BlockUndo>), ValidationError> {
+        let mut blocks = self.chain.iter()
+            .enumerate()
+            .map(|(height, block)| block.map_err(|err| ValidationError { height, reason: InvalidBlock::Unreadable(err.to_string()) }));
+
+        let genesis = blocks.next().expect("chain always has a genesis block")?;
+        if !genesis.header.is_genesis(&self.params) || !genesis.transactions.is_empty() {
+            return Err(ValidationError { height: 0, reason: InvalidBlock::BadGenesis });
+        }
+
+        let mut accounts = Accounts::new();
+        let mut chain_work = vec![genesis.get_work()];
+        let mut undo = vec![BlockUndo::default()];
+        let mut prev_block = genesis;
+        for (height, block) in (1..).zip(blocks) {
+            let block = block?;
+            let to_error = |reason| ValidationError { height, reason };
+
+            let target = self.expected_target(height, &prev_block)
+                .map_err(|err| to_error(InvalidBlock::Unreadable(err.to_string())))?;
+            block.validate(&prev_block, height, target, &self.params).map_err(to_error)?;
+            undo.push(block.apply_to(&mut accounts).map_err(to_error)?);
+            chain_work.push(chain_work[height - 1].saturating_add(block.get_work()));
+            prev_block = block;
+        }
+        Ok((accounts, chain_work, undo))
+    }
+
+    /// Disconnects every block above `height` from the main chain, reverting the accounts with the stored undo data.
+    /// The disconnected blocks are kept as a side branch, so branches forking from them can still be adopted,
+    /// and their transactions go back to the mempool. Returns the disconnected blocks, lowest first.
+    pub fn rollback_to(&mut self, height: usize) -> Result<Vec<Block>, StorageError> {
+        let disconnected = self.disconnect_to(height)?;
+        let orphaned = disconnected.iter().flat_map(|side_block| side_block.block.transactions.iter().cloned()).collect();
+        self.accounts.clear_all_pending();
+        print_dropped(&self.mempool.readmit(orphaned, &mut self.accounts, self.chain.len() as u64));
+        Ok(disconnected.into_iter()
+            .map(|side_block| {
+                self.tree.insert(side_block.block.clone(), side_block.height, side_block.chain_work);
+                side_block.block
+            })
+            .collect())
+    }
+
+    /// Reverts the accounts to the main chain block at `height` and removes the blocks above it from the store.
+    /// Returns the removed blocks, lowest first, with their heights and chain work so they can join the block tree.
+    fn disconnect_to(&mut self, height: usize) -> Result<Vec<SideBlock>, StorageError> {
+        // read everything first so a missing record leaves the accounts untouched
+        let mut disconnected = Vec::new();
+        for block_height in height + 1..self.chain.len() {
+            let undo = self.chain.get_undo(block_height)?
+                .ok_or(StorageError::Corrupt(format!("missing undo data for block {block_height}")))?;
+            let block = self.chain.get(block_height)?.expect("height is below the chain length");
+            disconnected.push((SideBlock { block, height: block_height, chain_work: self.chain_work[block_height] }, undo));
+        }
+        for (_, undo) in disconnected.iter().rev() {
+            self.accounts.undo_block(undo);
+        }
+        self.chain.truncate(height + 1)?;
+        self.chain_work.truncate(height + 1);
+        Ok(disconnected.into_iter().map(|(side_block, _)| side_block).collect())
+    }
+
+    /// Applies a block on top of the main chain tip, storing it with its undo data.
+    fn connect(&mut self, block: Block, chain_work: U256) -> Result<(), BlockError> {
+        let height = self.chain.len();
+        let undo = block.apply_to(&mut self.accounts).map_err(|reason| ValidationError { height, reason })?;
+        self.store_block(block, &undo, chain_work)?;
+        Ok(())
+    }
+
+    /// Stores a block that was just applied to the accounts together with its undo data.
+    /// If storing fails the accounts are reverted and the store is cut back, so the chain stays at its old tip.
+    fn store_block(&mut self, block: Block, undo: &BlockUndo, chain_work: U256) -> Result<(), StorageError> {
+        let height = self.chain.len();
+        if let Err(err) = self.chain.append(block).and_then(|()| self.chain.append_undo(undo)) {
+            self.accounts.undo_block(undo);
+            self.chain.truncate(height)?;
+            return Err(err);
+        }
+        self.chain_work.push(chain_work);
+        Ok(())
+    }
+
+    /// Adds a block mined elsewhere. A block on a branch with more work than the main chain makes that branch the main chain,
+    /// otherwise it is kept on a side branch in case the branch becomes the heaviest later.
+    pub fn submit_block(&mut self, block: Block) -> Result<BlockStatus, BlockError> {
+        let hash = block.get_hash();
+        if self.chain.height_of(&hash).is_some() || self.tree.contains(&hash) {
+            return Ok(BlockStatus::AlreadyKnown);
+        }
+
+        let (parent, parent_height, parent_work) = self.known_block(&block.header.pre_hash)?
+            .ok_or(BlockError::UnknownParent(block.header.pre_hash))?;
+        let height = parent_height + 1;
+        let target = self.expected_target(height, &parent)?;
+        block.validate(&parent, height, target, &self.params).map_err(|reason| ValidationError { height, reason })?;
+
+        // on equal work the branch seen first stays the main chain
+        let chain_work = parent_work.saturating_add(block.get_work());
+        self.tree.insert(block, height, chain_work);
+        if chain_work <= self.get_total_work() {
+            return Ok(BlockStatus::SideBranch);
+        }
+        let disconnected = self.reorganize(&hash)?;
+        Ok(BlockStatus::NewTip { disconnected })
+    }
+
+    /// Submits every block of another chain that is not known yet, adopting it if it has more work.
+    pub fn sync_from<T: BlockStore>(&mut self, chain: &Chain<T>) -> Result<(), BlockError> {
+        for block in chain.chain.iter() {
+            self.submit_block(block?)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the side branch ending at `tip` the main chain. The main chain is disconnected down to the fork point with its undo data
+    /// and the branch is connected on top. If a block of the branch spends more than its senders have, the old main chain is
+    /// connected again and the block is dropped along with the rest of the branch. Disconnected blocks move to the block tree
+    /// and their transactions go back to the mempool. Returns the number of disconnected blocks.
+    fn reorganize(&mut self, tip: &Hash256) -> Result<usize, BlockError> {
+        let (branch, fork_hash) = self.tree.branch(tip);
+        let fork_height = self.chain.height_of(&fork_hash).ok_or(BlockError::UnknownParent(fork_hash))?;
+        let disconnected = self.disconnect_to(fork_height)?;
+
+        let mut result = Ok(disconnected.len());
+        for (index, hash) in branch.iter().enumerate() {
+            let side_block = self.tree.remove(hash).expect("branch blocks are in the tree");
+            if let Err(err) = self.connect(side_block.block, side_block.chain_work) {
+                for hash in &branch[index + 1..] {
+                    self.tree.remove(hash);
+                }
+                for side_block in self.disconnect_to(fork_height)? {
+                    self.tree.insert(side_block.block, side_block.height, side_block.chain_work);
+                }
+                result = Err(err);
+                break;
+            }
+        }
+
+        let mut orphaned = Vec::new();
+        for side_block in disconnected {
+            if result.is_ok() {
+                orphaned.extend(side_block.block.transactions.iter().cloned());
+                self.tree.insert(side_block.block, side_block.height, side_block.chain_work);
+            } else {
+                self.connect(side_block.block, side_block.chain_work)?;
+            }
+        }
+        self.accounts.clear_all_pending();
+        print_dropped(&self.mempool.readmit(orphaned, &mut self.accounts, self.chain.len() as u64));
+        result
+    }
+
+    /// A block on the main chain or a side branch with its height and the work of its chain up to it.
+    fn known_block(&self, hash: &Hash256) -> Result<Option<(Block, usize, U256)>, StorageError> {
+        if let Some(height) = self.chain.height_of(hash) {
+            let block = self.chain.get(height)?.expect("height is below the chain length");
+            return Ok(Some((block, height, self.chain_work[height])));
+        }
+        Ok(self.tree.get(hash).map(|side_block| (side_block.block.clone(), side_block.height, side_block.chain_work)))
+    }
+
+    /// The block at `height` on the chain ending with the block `hash`, which may be on a side branch.
+    fn ancestor(&self, mut hash: Hash256, height: usize) -> Result<Block, BlockError> {
+        while let Some(side_block) = self.tree.get(&hash) {
+            if side_block.height == height {
+                return Ok(side_block.block.clone());
+            }
+            hash = side_block.block.header.pre_hash;
+        }
+        match self.chain.height_of(&hash) {
+            Some(fork_height) if fork_height >= height => Ok(self.chain.get(height)?.expect("height is below the chain length")),
+            _ => Err(BlockError::UnknownParent(hash)),
+        }
+    }
+
+    /// Target the block at `height` has to be mined with. It only changes at retarget heights,
+    /// based on the time it took to mine the previous `retarget_interval` blocks.
+    fn expected_target(&self, height: usize, prev_block: &Block) -> Result<CompactTarget, BlockError> {
+        if !self.params.is_retarget_height(height) {
+            return Ok(prev_block.get_target());
+        }
+
+        let first_height = self.params.retarget_window_start(height);
+        let first_block = self.ancestor(prev_block.get_hash(), first_height)?;
+        let actual_span = prev_block.header.time.saturating_sub(first_block.header.time);
+        Ok(self.params.retarget(prev_block.get_target(), height - 1 - first_height, actual_span))
+    }
+
+    fn last_block(&self) -> Result<Block, StorageError> {
+        self.chain.tip().map(|block| block.expect("chain always has a genesis block"))
+    }
+
     pub fn print_all_blockchain(&self) {
-        for block in &self.chain {
-            println!("{block}")
+        for block in self.chain.iter() {
+            match block {
+                Ok(block) => println!("{block}"),
+                Err(err) => println!("{err}"),
+            }
         }
     }
 
     pub fn print_current(&self) {
         println!();
-        println!("Last block:\n{}", &self.chain[self.length - 1]);
+        match self.last_block() {
+            Ok(last_block) => println!("Last block:\n{}", last_block),
+            Err(err) => println!("{}", err),
+        }
         println!();
 
-        let transactions = &self.curr_trans;
+        let transactions = self.mempool.block_template(usize::MAX, usize::MAX);
 
         if transactions.is_empty() {println!("No new transactions.")}
         else {println!("Current transactions:");}
@@ -68,21 +319,33 @@ impl Chain {
 
     }
 
-    pub fn mine(&mut self, miner_address: u128, num_threads: u8) {
-        self.miner_reward_transaction(miner_address, self.reward);
+    /// Mines the pending transactions with the highest fee rates that fit into a new block, paying the miner the block subsidy plus their fees.
+    /// Mined transactions leave the mempool together with any that are no longer valid on top of the new block.
+    pub fn mine(&mut self, miner_address: u128, num_threads: u8) -> Result<(), BlockError> {
+        let last_block = self.last_block()?;
+        let height = self.chain.len();
 
-        let last_block = &self.chain[self.length - 1];
-        let curr_trans = self.curr_trans.clone();
-        self.curr_trans = Vec::new();
+        let target = self.expected_target(height, &last_block)?;
 
-        let mut new_block = Block::new_block(last_block, curr_trans.clone());
-        self.pow(&mut new_block, num_threads);
+        // the coinbase amount is a fixed size integer, so the size of a block with only the coinbase is known up front
+        let coinbase_only = Block::new_block(&last_block, vec![Transaction::coinbase(miner_address, 0, height as u64)], target, Hash256::ZERO);
+        let mut curr_trans = self.mempool.block_template(
+            self.params.max_block_size.saturating_sub(coinbase_only.get_size()),
+            self.params.max_block_transactions.saturating_sub(1),
+        );
+        let reward = curr_trans.iter().fold(self.params.block_subsidy(height), |total, transaction| total.saturating_add(transaction.get_fee()));
+        curr_trans.insert(0, Transaction::coinbase(miner_address, reward, height as u64));
 
-        new_block.header.time = get_time();
-        self.accounts.update_accounts(curr_trans);
-        self.length += 1;
+        // the header commits to the state after the block, so the transactions are applied before mining
+        let undo = self.accounts.apply_block(&curr_trans).map_err(|reason| ValidationError { height, reason })?;
+        let mut new_block = Block::new_block(&last_block, curr_trans.clone(), target, self.accounts.state_root());
+        self.pow(&mut new_block, num_threads);
 
-        self.chain.push(new_block)
+        let chain_work = self.get_total_work().saturating_add(new_block.get_work());
+        self.store_block(new_block, &undo, chain_work)?;
+        print_dropped(&self.mempool.remove_confirmed(&curr_trans, &mut self.accounts));
+        print_dropped(&self.mempool.advance_to(self.chain.len() as u64, &mut self.accounts));
+        Ok(())
     }
 
     pub fn pow(&self, block: &mut Block, num_threads: u8) {
@@ -91,7 +354,7 @@ impl Chain {
         use std::thread;
         use std::sync::mpsc;
 
-        let difficulty = self.difficulty as u128;
+        let target = block.get_target();
 
         let (tx, rx) = mpsc::channel();
 
@@ -110,8 +373,8 @@ impl Chain {
                 local_header.nonce = thread_id as u128;
 
                 while !found.load(Ordering::Relaxed) {
-                    let hash = Sha128::block_hash(&local_header);
-                    if hash >> (128 - difficulty) == 0 {
+                    let hash = Hash256::block_hash(&local_header);
+                    if target.is_met_by(&hash) {
                         if !found.swap(true, Ordering::Relaxed) {
                             let _ = tx.send(local_header.nonce);
                         }
@@ -136,24 +399,58 @@ impl Chain {
         }
     }
 
-    pub fn miner_reward_transaction(&mut self, miner_address: u128, reward: u8) {
-        self.sign_transaction(31, miner_address, reward as u128);
-    }
-    pub fn sign_transaction(&mut self, private_key: u128, receiver_address: u128, amount: u128) {
-        match &self.accounts.sign_transaction(private_key, receiver_address, amount) {
-            Ok(transaction) => {
+    /// Signs a transaction and adds it to the mempool. It is dropped if it is not mined by the `expiry` height.
+    pub fn sign_transaction(&mut self, keypair: &Keypair, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) {
+        let transaction = self.accounts.sign_transaction(keypair, receiver_address, amount, fee, expiry);
+        match self.mempool.insert(transaction.clone(), &mut self.accounts) {
+            Ok(dropped) => {
+                print_dropped(&dropped);
                 println!("New Transaction:\n{}", transaction);
-                self.curr_trans.push(transaction.clone())
             },
             Err(err) => println!("{}", err),
         }
     }
 
-    pub fn get_curr_transactions(&self) -> &Vec<Transaction> {
-        &self.curr_trans
+    /// Signs a transaction reusing the nonce of a pending transaction, replacing it in the mempool if the fee is higher.
+    pub fn replace_transaction(&mut self, keypair: &Keypair, nonce: u64, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) {
+        let transaction = Transaction::generate_transaction(keypair, receiver_address, amount, fee, nonce, expiry);
+        match self.mempool.insert(transaction.clone(), &mut self.accounts) {
+            Ok(dropped) => {
+                print_dropped(&dropped);
+                println!("Replacement Transaction:\n{}", transaction);
+            },
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Adds a transaction signed elsewhere to the mempool after checking its signature and the sender's balance.
+    /// Returns the pending transactions it evicted or replaced.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<Vec<Dropped>, TransactionError> {
+        self.mempool.insert(transaction, &mut self.accounts)
+    }
+
+    /// Headers of the main chain from `start` up to the tip, for clients that follow the chain without the transactions.
+    pub fn get_headers(&self, start: usize) -> Result<Vec<BlockHeader>, StorageError> {
+        (start..self.chain.len())
+            .map(|height| Ok(self.chain.get(height)?.expect("height is below the chain length").header))
+            .collect()
+    }
+
+    pub fn get_mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Sum of the work of every block in the chain.
+    pub fn get_total_work(&self) -> U256 {
+        *self.chain_work.last().expect("chain always has a genesis block")
     }
 
-    pub fn get_chain(&self) -> &Vec<Block> {
+    /// Side branches known besides the main chain.
+    pub fn get_block_tree(&self) -> &BlockTree {
+        &self.tree
+    }
+
+    pub fn get_store(&self) -> &S {
         &self.chain
     }
 
@@ -162,6 +459,12 @@ impl Chain {
     }
 }
 
+fn print_dropped(dropped: &[Dropped]) {
+    for dropped in dropped {
+        println!("{dropped}");
+    }
+}
+
 fn get_time() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -171,39 +474,37 @@ fn get_time() -> u128 {
 
 #[derive(PartialEq, Clone)]
 pub struct BlockHeader {
+    version: u32,
     time: u128,
     nonce: u128,
-    pre_hash: u128,
-    merkle: u128,
-    difficulty: u8,
+    pre_hash: Hash256,
+    merkle: Hash256,
+    /// Root of the state tree over the accounts after applying the block.
+    state_root: Hash256,
+    bits: CompactTarget,
 }
 
-impl BlockHeader {
-    pub fn get_data(&self) -> u128 {
-        self.nonce % OVERFLOW_PROTECTION +
-            self.pre_hash % OVERFLOW_PROTECTION +
-            self.merkle % OVERFLOW_PROTECTION +
-            self.difficulty as u128
-    }
-}
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 pub struct Block {
     header: BlockHeader,
     transactions: Vec<Transaction>
 }
 
 impl Block {
-     fn get_genesis_block(difficulty: u8) -> Self {
-        let time = get_time();
-        let pre_hash: u128 = 0;
+    /// The first block of every chain built with `params`, the same on every node.
+    fn get_genesis_block(params: &ConsensusParams) -> Self {
+        let time = params.genesis_time;
+        let pre_hash = Hash256::ZERO;
         let nonce: u128 = 0;
-        let merkle: u128 = 0;
+        let merkle = Hash256::ZERO;
         let header = BlockHeader {
+            version: BLOCK_VERSION,
             time,
             nonce,
             pre_hash,
             merkle,
-            difficulty
+            state_root: Hash256::ZERO,
+            bits: params.initial_target,
         };
 
         let transactions: Vec<Transaction> = Vec::new();
@@ -214,44 +515,726 @@ impl Block {
         }
     }
 
-    fn new_block(prev_block: &Block, transactions: Vec<Transaction>) -> Block {
+    /// Block on top of `prev_block`, stamped with the current time unless that is not after the parent's,
+    /// for example after the clock was set back.
+    fn new_block(prev_block: &Block, transactions: Vec<Transaction>, bits: CompactTarget, state_root: Hash256) -> Block {
         Block {
             header: BlockHeader {
-                time: 0,
+                version: BLOCK_VERSION,
+                time: get_time().max(prev_block.header.time + 1),
                 nonce: 0,
                 pre_hash: prev_block.get_hash(),
-                merkle: Transaction::get_merkle(&transactions),
-                difficulty: prev_block.get_difficulty(),
+                merkle: Transaction::get_merkle_tree(&transactions).root(),
+                state_root,
+                bits,
             },
             transactions,
         }
     }
 
-    fn get_hash(&self) -> u128 {
-        Sha128::block_hash(&self.header)
+    /// Checks a block at `height` against its parent: linkage, timestamp, target, proof of work, merkle root, transactions and their expiry, and the coinbase,
+    /// which can not pay more than the block subsidy plus the fees of the other transactions. The size and transaction count are
+    /// limited by `params`.
+    /// Balances and the state root are not checked here since they depend on the whole chain, see `apply_to`.
+    pub fn validate(&self, prev_block: &Block, height: usize, target: CompactTarget, params: &ConsensusParams) -> Result<(), InvalidBlock> {
+        self.header.validate(&prev_block.header, target)?;
+        if self.transactions.len() > params.max_block_transactions {
+            return Err(InvalidBlock::TooManyTransactions { max: params.max_block_transactions, found: self.transactions.len() });
+        }
+        if self.get_size() > params.max_block_size {
+            return Err(InvalidBlock::TooLarge { max: params.max_block_size, found: self.get_size() });
+        }
+        if self.header.merkle != Transaction::get_merkle_tree(&self.transactions).root() {
+            return Err(InvalidBlock::BadMerkleRoot);
+        }
+        for transaction in &self.transactions {
+            transaction.validate()?;
+            if transaction.is_expired_at(height as u64) {
+                return Err(InvalidBlock::ExpiredTransaction(transaction.get_hash()));
+            }
+        }
+
+        let (coinbase, transactions) = self.transactions.split_first().ok_or(InvalidBlock::MissingCoinbase)?;
+        let coinbase_height = coinbase.get_coinbase_height().ok_or(InvalidBlock::MissingCoinbase)?;
+        if coinbase_height != height as u64 {
+            return Err(InvalidBlock::BadCoinbaseHeight { expected: height as u64, found: coinbase_height });
+        }
+        if transactions.iter().any(Transaction::is_coinbase) {
+            return Err(InvalidBlock::DuplicateCoinbase);
+        }
+        let allowed = transactions.iter().fold(params.block_subsidy(height), |total, transaction| total.saturating_add(transaction.get_fee()));
+        if coinbase.get_amount() > allowed {
+            return Err(InvalidBlock::BadReward { allowed, found: coinbase.get_amount() });
+        }
+        Ok(())
+    }
+
+    /// Applies the transactions to `accounts` and checks the state root against the result, leaving `accounts` untouched if either fails.
+    fn apply_to(&self, accounts: &mut Accounts) -> Result<BlockUndo, InvalidBlock> {
+        let undo = accounts.apply_block(&self.transactions)?;
+        let state_root = accounts.state_root();
+        if self.header.state_root != state_root {
+            accounts.undo_block(&undo);
+            return Err(InvalidBlock::BadStateRoot { expected: state_root, found: self.header.state_root });
+        }
+        Ok(undo)
+    }
+
+    pub fn get_hash(&self) -> Hash256 {
+        Hash256::block_hash(&self.header)
+    }
+
+    pub fn get_prev_hash(&self) -> Hash256 {
+        self.header.pre_hash
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Proof that the transaction at `index` is committed to by the merkle root in the header.
+    pub fn prove_transaction(&self, index: usize) -> Option<MerkleProof> {
+        Transaction::get_merkle_tree(&self.transactions).prove(index)
+    }
+
+    pub fn get_merkle_root(&self) -> Hash256 {
+        self.header.merkle
+    }
+
+    /// Size of the encoded block in bytes.
+    pub fn get_size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    pub fn get_target(&self) -> CompactTarget {
+        self.header.bits
+    }
+
+    pub fn get_state_root(&self) -> Hash256 {
+        self.header.state_root
     }
 
-    fn get_difficulty(&self) -> u8 {
-        self.header.difficulty
+    /// Work needed to mine this block, summed up to compare chains.
+    pub fn get_work(&self) -> U256 {
+        self.header.bits.work()
+    }
+}
+
+impl BlockHeader {
+    /// Checks the header against its parent: version, timestamp, linkage, target and proof of work.
+    /// This is everything that can be checked without the transactions of the block.
+    pub fn validate(&self, prev_header: &BlockHeader, target: CompactTarget) -> Result<(), InvalidBlock> {
+        if self.version != BLOCK_VERSION {
+            return Err(InvalidBlock::BadVersion(self.version));
+        }
+        if self.time <= prev_header.time || self.time > get_time() + MAX_FUTURE_BLOCK_TIME {
+            return Err(InvalidBlock::BadTimestamp);
+        }
+        if self.pre_hash != prev_header.get_hash() {
+            return Err(InvalidBlock::BadPrevHash);
+        }
+        if self.bits != target {
+            return Err(InvalidBlock::BadTarget { expected: target, found: self.bits });
+        }
+        if !target.is_met_by(&self.get_hash()) {
+            return Err(InvalidBlock::InsufficientWork);
+        }
+        Ok(())
+    }
+
+    /// Whether this is the genesis header of a chain built with `params`.
+    pub fn is_genesis(&self, params: &ConsensusParams) -> bool {
+        *self == Block::get_genesis_block(params).header
+    }
+
+    pub fn get_hash(&self) -> Hash256 {
+        Hash256::block_hash(self)
+    }
+
+    pub fn get_prev_hash(&self) -> Hash256 {
+        self.pre_hash
+    }
+
+    pub fn get_time(&self) -> u128 {
+        self.time
+    }
+
+    pub fn get_merkle_root(&self) -> Hash256 {
+        self.merkle
+    }
+
+    pub fn get_state_root(&self) -> Hash256 {
+        self.state_root
+    }
+
+    pub fn get_target(&self) -> CompactTarget {
+        self.bits
+    }
+
+    pub fn get_work(&self) -> U256 {
+        self.bits.work()
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&self.time.to_be_bytes());
+        bytes.extend_from_slice(&self.nonce.to_be_bytes());
+        bytes.extend_from_slice(self.pre_hash.as_bytes());
+        bytes.extend_from_slice(self.merkle.as_bytes());
+        bytes.extend_from_slice(self.state_root.as_bytes());
+        bytes.extend_from_slice(&self.bits.to_bits().to_be_bytes());
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            version: reader.read_u32()?,
+            time: reader.read_u128()?,
+            nonce: reader.read_u128()?,
+            pre_hash: Hash256::from_bytes(reader.read_array()?),
+            merkle: Hash256::from_bytes(reader.read_array()?),
+            state_root: Hash256::from_bytes(reader.read_array()?),
+            bits: CompactTarget::from_bits(reader.read_u32()?),
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        self.header.encode_to(bytes);
+        bytes.extend_from_slice(&(self.transactions.len() as u32).to_be_bytes());
+        for transaction in &self.transactions {
+            transaction.encode_to(bytes);
+        }
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode_from(reader)?;
+        let transaction_count = reader.read_length(Transaction::MIN_ENCODED_LENGTH)?;
+        let transactions = (0..transaction_count)
+            .map(|_| Transaction::decode_from(reader))
+            .collect::<Result<Vec<Transaction>, DecodeError>>()?;
+
+        Ok(Block {
+            header,
+            transactions,
+        })
     }
 }
 
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f,
-               "| previous block's hash: {:032x}\n\
+               "| version: {}\n\
+               | previous block's hash: {}\n\
                | mining time: {}\n\
                | nonce: {}\n\
-               | merkle: {:032x}\n\
-               | difficulty: {}\n\
-               | block hash: {:032x}",
+               | merkle: {}\n\
+               | state root: {}\n\
+               | target bits: {}\n\
+               | block hash: {}",
+               self.header.version,
                self.header.pre_hash,
                self.header.time,
                self.header.nonce,
                self.header.merkle,
-               self.header.difficulty,
+               self.header.state_root,
+               self.header.bits,
                self.get_hash(),
                )
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::ENCODING_VERSION;
+    use crate::keys::SIGNATURE_LENGTH;
+    use crate::merkle;
+    use crate::mempool::DropReason;
+    use crate::test_utils::{mine_blocks, test_chain, test_params, MINER_KEY, RECEIVER};
+
+    const UNKNOWN_KEY: u128 = 99;
+
+    /// Memory store that can be told to fail writing undo data.
+    #[derive(Default)]
+    struct FailingStore {
+        store: MemoryStore,
+        fail_undo: bool,
+    }
+
+    impl BlockStore for FailingStore {
+        fn append(&mut self, block: Block) -> Result<(), StorageError> {
+            self.store.append(block)
+        }
+
+        fn get(&self, height: usize) -> Result<Option<Block>, StorageError> {
+            self.store.get(height)
+        }
+
+        fn height_of(&self, hash: &Hash256) -> Option<usize> {
+            self.store.height_of(hash)
+        }
+
+        fn len(&self) -> usize {
+            self.store.len()
+        }
+
+        fn append_undo(&mut self, undo: &BlockUndo) -> Result<(), StorageError> {
+            if self.fail_undo {
+                return Err(StorageError::Corrupt("undo log is not writable".to_string()));
+            }
+            self.store.append_undo(undo)
+        }
+
+        fn get_undo(&self, height: usize) -> Result<Option<BlockUndo>, StorageError> {
+            self.store.get_undo(height)
+        }
+
+        fn undo_len(&self) -> usize {
+            self.store.undo_len()
+        }
+
+        fn truncate(&mut self, height: usize) -> Result<(), StorageError> {
+            self.store.truncate(height)
+        }
+    }
+
+    /// Mines a block on the chain tip with a coinbase paying the subsidy followed by `transactions`, without applying it.
+    /// The state root is the one the transactions produce, or zero if they can not be applied.
+    fn craft_block(chain: &mut Chain, transactions: Vec<Transaction>) -> Block {
+        let last_block = chain.last_block().unwrap();
+        let height = chain.chain.len();
+        let coinbase = Transaction::coinbase(Keypair::from_private_key(MINER_KEY).address(), chain.params.block_subsidy(height), height as u64);
+        let transactions: Vec<Transaction> = [coinbase].into_iter().chain(transactions).collect();
+        let state_root = match chain.accounts.apply_block(&transactions) {
+            Ok(undo) => {
+                let state_root = chain.accounts.state_root();
+                chain.accounts.undo_block(&undo);
+                state_root
+            },
+            Err(_) => Hash256::ZERO,
+        };
+        let target = chain.expected_target(height, &last_block).unwrap();
+        let mut block = Block::new_block(&last_block, transactions, target, state_root);
+        chain.pow(&mut block, 1);
+        block
+    }
+
+    fn unknown_sender_transaction() -> Transaction {
+        Transaction::generate_transaction(&Keypair::from_private_key(UNKNOWN_KEY), RECEIVER, 10, 0, 0, None)
+    }
+
+    #[test]
+    fn submitted_block_with_unknown_sender_is_rejected() {
+        let mut chain = test_chain();
+        let transaction = unknown_sender_transaction();
+        let block = craft_block(&mut chain, vec![transaction.clone()]);
+        let miner_amount = chain.accounts.get_account_amount(&Keypair::from_private_key(MINER_KEY).address()).unwrap();
+
+        let err = chain.submit_block(block).unwrap_err();
+        assert!(matches!(
+            err,
+            BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::UnknownSender(hash) }) if hash == transaction.get_hash()
+        ));
+        assert_eq!(chain.chain.len(), 2);
+        assert!(chain.accounts.get_account_amount(&RECEIVER).is_err());
+        assert_eq!(chain.accounts.get_account_amount(&Keypair::from_private_key(MINER_KEY).address()).unwrap(), miner_amount);
+    }
+
+    #[test]
+    fn stored_block_with_unknown_sender_fails_replay() {
+        let mut chain = test_chain();
+        let block = craft_block(&mut chain, vec![unknown_sender_transaction()]);
+        let Chain { chain: mut store, params, .. } = chain;
+        store.append(block).unwrap();
+
+        match Chain::with_params(store, params) {
+            Err(StorageError::InvalidChain(err)) => assert!(matches!(err.reason, InvalidBlock::UnknownSender(_)) && err.height == 2),
+            _ => panic!("chain with an unknown sender was accepted"),
+        }
+    }
+
+    #[test]
+    fn unknown_sender_leaves_accounts_untouched() {
+        let mut accounts = Accounts::new();
+        let coinbase = Transaction::coinbase(RECEIVER, 50, 1);
+        let result = accounts.apply_block(&[coinbase, unknown_sender_transaction()]);
+
+        assert!(matches!(result, Err(InvalidBlock::UnknownSender(_))));
+        assert!(accounts.get_account_amount(&RECEIVER).is_err());
+    }
+
+    #[test]
+    fn sender_funded_earlier_in_the_block_is_known() {
+        let mut chain = test_chain();
+        let sender = Keypair::from_private_key(UNKNOWN_KEY);
+        let funding = Transaction::generate_transaction(&Keypair::from_private_key(MINER_KEY), sender.address(), 20, 0, 0, None);
+        let block = craft_block(&mut chain, vec![funding, unknown_sender_transaction()]);
+
+        assert_eq!(chain.submit_block(block).unwrap(), BlockStatus::NewTip { disconnected: 0 });
+        assert_eq!(chain.accounts.get_account_amount(&sender.address()).unwrap(), 10);
+        assert_eq!(chain.accounts.get_account_amount(&RECEIVER).unwrap(), 10);
+    }
+
+    /// Transfers from the miner whose signature does not cover them: one with the amount changed after signing
+    /// and one signed by another key.
+    fn forged_transactions() -> [Transaction; 2] {
+        let transaction = Transaction::generate_transaction(&Keypair::from_private_key(MINER_KEY), RECEIVER, 10, 1, 0, None);
+        let bytes = transaction.to_bytes();
+        let signature_start = bytes.len() - SIGNATURE_LENGTH;
+
+        // version, kind tag, sender key, nonce and receiver come before the amount
+        let mut changed_amount = bytes.clone();
+        changed_amount[58..74].copy_from_slice(&11u128.to_be_bytes());
+        let mut other_signer = bytes.clone();
+        let signature = Keypair::from_private_key(UNKNOWN_KEY).sign(&bytes[..signature_start]);
+        other_signer[signature_start..].copy_from_slice(signature.as_bytes());
+
+        [changed_amount, other_signer].map(|bytes| Transaction::from_bytes(&bytes).unwrap())
+    }
+
+    #[test]
+    fn transactions_with_bad_signatures_are_rejected() {
+        let mut chain = test_chain();
+        let forged = forged_transactions();
+        assert_eq!(forged[0].get_amount(), 11);
+        for forged in forged {
+            assert_eq!(forged.get_sender_address(), Some(Keypair::from_private_key(MINER_KEY).address()));
+
+            let block = craft_block(&mut chain, vec![forged.clone()]);
+            assert!(matches!(
+                chain.submit_block(block),
+                Err(BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::BadSignature(hash) })) if hash == forged.get_hash()
+            ));
+
+            let err = chain.mempool.insert(forged, &mut chain.accounts).unwrap_err();
+            assert_eq!(err.0, "Invalid signature");
+            assert!(chain.mempool.is_empty());
+        }
+        assert_eq!(chain.chain.len(), 2);
+    }
+
+    #[test]
+    fn block_with_wrong_state_root_is_rejected() {
+        let mut chain = test_chain();
+        let mut block = craft_block(&mut chain, Vec::new());
+        let state_root = block.header.state_root;
+        block.header.state_root = Hash256::digest(b"wrong");
+        chain.pow(&mut block, 1);
+        let miner_amount = chain.accounts.get_account_amount(&Keypair::from_private_key(MINER_KEY).address()).unwrap();
+
+        let err = chain.submit_block(block).unwrap_err();
+        assert!(matches!(
+            err,
+            BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::BadStateRoot { expected, .. } }) if expected == state_root
+        ));
+        assert_eq!(chain.accounts.get_account_amount(&Keypair::from_private_key(MINER_KEY).address()).unwrap(), miner_amount);
+    }
+
+    #[test]
+    fn mined_blocks_commit_to_the_accounts() {
+        let mut chain = test_chain();
+        let miner = Keypair::from_private_key(MINER_KEY);
+        chain.sign_transaction(&miner, RECEIVER, 10, 1, None);
+        mine_blocks(&mut chain, 1);
+
+        let state_root = chain.last_block().unwrap().get_state_root();
+        assert_eq!(state_root, chain.accounts.state_root());
+        let receiver_proof = chain.accounts.prove_account(&RECEIVER);
+        assert!(receiver_proof.verify(&state_root, RECEIVER, Some((10, 0))));
+        assert!(!receiver_proof.verify(&state_root, RECEIVER, Some((11, 0))));
+        assert!(chain.accounts.prove_account(&6).verify(&state_root, 6, None));
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn mined_transactions_are_proven_against_the_header() {
+        let mut chain = test_chain();
+        let miner = Keypair::from_private_key(MINER_KEY);
+        for fee in 1..=2 {
+            chain.sign_transaction(&miner, RECEIVER, 10, fee, None);
+        }
+        mine_blocks(&mut chain, 1);
+
+        let block = chain.last_block().unwrap();
+        assert_eq!(block.get_transactions().len(), 3);
+        for (index, transaction) in block.get_transactions().iter().enumerate() {
+            let proof = block.prove_transaction(index).unwrap();
+            assert!(merkle::verify(&block.get_merkle_root(), &transaction.get_hash(), &proof));
+        }
+        assert!(block.prove_transaction(3).is_none());
+    }
+
+    #[test]
+    fn rejects_retarget_windows_without_a_block_interval() {
+        for retarget_interval in [0, 1] {
+            let params = ConsensusParams { retarget_interval, ..ConsensusParams::default() };
+            assert!(matches!(Chain::with_params(MemoryStore::default(), params), Err(StorageError::InvalidParams(_))));
+        }
+    }
+
+    #[test]
+    fn mines_after_a_parent_from_the_future() {
+        let mut chain = test_chain();
+        let mut block = craft_block(&mut chain, Vec::new());
+        block.header.time = get_time() + MAX_FUTURE_BLOCK_TIME / 2;
+        chain.pow(&mut block, 1);
+        let parent_time = block.header.time;
+        assert_eq!(chain.submit_block(block).unwrap(), BlockStatus::NewTip { disconnected: 0 });
+
+        mine_blocks(&mut chain, 1);
+        assert!(chain.last_block().unwrap().header.time > parent_time);
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn blocks_round_trip_through_the_encoding() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        let transfer = Transaction::generate_transaction(&miner, RECEIVER, 10, 1, 0, Some(40));
+        let block = craft_block(&mut chain, vec![transfer.clone()]);
+
+        let decoded = Block::from_bytes(&block.to_bytes()).unwrap();
+        assert!(decoded == block);
+        assert_eq!(decoded.get_transactions()[1].get_hash(), transfer.get_hash());
+        assert!(BlockHeader::from_bytes(&block.header.to_bytes()).unwrap() == block.header);
+
+        let undo = chain.accounts.apply_block(block.get_transactions()).unwrap();
+        assert_eq!(BlockUndo::from_bytes(&undo.to_bytes()).unwrap(), undo);
+    }
+
+    #[test]
+    fn rejects_malformed_encodings() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        let transfer = Transaction::generate_transaction(&miner, RECEIVER, 10, 1, 0, Some(40));
+        let block = craft_block(&mut chain, vec![transfer.clone()]);
+        let bytes = block.to_bytes();
+        let decode = |bytes: &[u8]| Block::from_bytes(bytes).err();
+
+        let mut versioned = bytes.clone();
+        versioned[0] = ENCODING_VERSION + 1;
+        assert_eq!(decode(&versioned), Some(DecodeError::UnsupportedVersion(ENCODING_VERSION + 1)));
+        assert_eq!(decode(&bytes[..bytes.len() - 1]), Some(DecodeError::UnexpectedEnd));
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert_eq!(decode(&padded), Some(DecodeError::TrailingBytes(1)));
+
+        // the transaction count follows the version byte and the header
+        let count_start = block.header.to_bytes().len();
+        let mut overlong = bytes.clone();
+        overlong[count_start..count_start + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decode(&overlong), Some(DecodeError::InvalidLength(u32::MAX as usize)));
+
+        let coinbase_start = count_start + 4;
+        let mut bad_kind = bytes.clone();
+        bad_kind[coinbase_start] = 7;
+        assert_eq!(decode(&bad_kind), Some(DecodeError::InvalidField("transaction kind")));
+
+        // the expiry tag and expiry height come right before the signature
+        let transfer_end = coinbase_start + block.get_transactions()[0].to_bytes().len() - 1 + transfer.to_bytes().len() - 1;
+        let mut bad_expiry = bytes.clone();
+        bad_expiry[transfer_end - SIGNATURE_LENGTH - 8 - 1] = 2;
+        assert_eq!(decode(&bad_expiry), Some(DecodeError::InvalidField("transaction expiry")));
+        assert_eq!(transfer_end, bytes.len());
+    }
+
+    #[test]
+    fn rejects_blocks_over_the_limits() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        let transactions = (0..2).map(|nonce| Transaction::generate_transaction(&miner, RECEIVER, 10, 1, nonce, None)).collect();
+        let block = craft_block(&mut chain, transactions);
+
+        chain.params.max_block_transactions = 2;
+        assert!(matches!(
+            chain.submit_block(block.clone()),
+            Err(BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::TooManyTransactions { max: 2, found: 3 } }))
+        ));
+        chain.params.max_block_transactions = 3;
+        chain.params.max_block_size = block.get_size() - 1;
+        assert!(matches!(
+            chain.submit_block(block.clone()),
+            Err(BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::TooLarge { found, .. } })) if found == block.get_size()
+        ));
+        chain.params.max_block_size = block.get_size();
+        assert_eq!(chain.submit_block(block).unwrap(), BlockStatus::NewTip { disconnected: 0 });
+    }
+
+    #[test]
+    fn transactions_over_the_limits_stay_in_the_mempool() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let params = ConsensusParams { max_block_transactions: 2, ..test_params() };
+        let mut chain = Chain::with_params(MemoryStore::default(), params).unwrap();
+        mine_blocks(&mut chain, 1);
+        for fee in [3, 2, 1] {
+            chain.sign_transaction(&miner, RECEIVER, 10, fee, None);
+        }
+
+        mine_blocks(&mut chain, 1);
+        assert_eq!(chain.last_block().unwrap().get_transactions().len(), 2);
+        assert_eq!(chain.mempool.len(), 2);
+
+        // room for the coinbase and one transfer but not two
+        let block = chain.last_block().unwrap();
+        chain.params.max_block_transactions = 500;
+        chain.params.max_block_size = block.get_size() + block.get_transactions()[1].to_bytes().len() / 2;
+        mine_blocks(&mut chain, 1);
+        assert_eq!(chain.last_block().unwrap().get_transactions().len(), 2);
+        assert_eq!(chain.mempool.len(), 1);
+
+        chain.params.max_block_size = ConsensusParams::default().max_block_size;
+        mine_blocks(&mut chain, 1);
+        assert!(chain.mempool.is_empty());
+        assert_eq!(chain.accounts.get_account_amount(&RECEIVER).unwrap(), 30);
+    }
+
+    #[test]
+    fn rejects_a_zero_target_block_time() {
+        let params = ConsensusParams { target_block_time: 0, ..test_params() };
+        assert!(matches!(Chain::with_params(MemoryStore::default(), params), Err(StorageError::InvalidParams(_))));
+    }
+
+    #[test]
+    fn retargets_at_the_end_of_each_window() {
+        let params = ConsensusParams { retarget_interval: 3, ..test_params() };
+        let mut chain = Chain::with_params(MemoryStore::default(), params.clone()).unwrap();
+        mine_blocks(&mut chain, 2);
+        assert!(chain.last_block().unwrap().get_target() == params.initial_target);
+
+        let mut block = craft_block(&mut chain, Vec::new());
+        let target = block.get_target();
+        // the window starts after the fixed genesis time, so blocks mined back to back make the target harder
+        assert!(target.to_target().unwrap() < params.initial_target.to_target().unwrap());
+        block.header.bits = params.initial_target;
+        chain.pow(&mut block, 1);
+        let err = chain.submit_block(block).unwrap_err();
+        assert!(matches!(
+            err,
+            BlockError::Invalid(ValidationError { height: 3, reason: InvalidBlock::BadTarget { expected, found } })
+                if expected == target && found == params.initial_target
+        ));
+
+        mine_blocks(&mut chain, 1);
+        assert!(chain.last_block().unwrap().get_target() == target);
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_a_zero_halving_interval() {
+        let params = ConsensusParams { halving_interval: 0, ..ConsensusParams::default() };
+        assert!(matches!(Chain::with_params(MemoryStore::default(), params), Err(StorageError::InvalidParams(_))));
+    }
+
+    #[test]
+    fn separately_started_chains_share_genesis_and_sync() {
+        let mut chain = test_chain();
+        let mut other = Chain::with_params(MemoryStore::default(), chain.params.clone()).unwrap();
+        assert!(other.get_headers(0).unwrap()[0] == chain.get_headers(0).unwrap()[0]);
+
+        mine_blocks(&mut other, 2);
+        chain.sync_from(&other).unwrap();
+        assert!(chain.last_block().unwrap() == other.last_block().unwrap());
+        assert_eq!(chain.get_block_tree().len(), 1);
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn rolled_back_blocks_stay_known_as_a_side_branch() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        mine_blocks(&mut chain, 2);
+        let first_block = chain.chain.get(1).unwrap().unwrap();
+
+        let mut other = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        other.submit_block(first_block.clone()).unwrap();
+        other.mine(RECEIVER, 1).unwrap();
+        other.mine(RECEIVER, 1).unwrap();
+        let branch: Vec<Block> = (2..4).map(|height| other.chain.get(height).unwrap().unwrap()).collect();
+
+        assert_eq!(chain.submit_block(branch[0].clone()).unwrap(), BlockStatus::SideBranch);
+        let disconnected = chain.rollback_to(0).unwrap();
+        assert_eq!(disconnected.len(), 3);
+        assert!(disconnected[0] == first_block);
+        assert_eq!(chain.chain.len(), 1);
+        assert!(chain.accounts.get_account_amount(&miner.address()).is_err());
+        assert_eq!(chain.get_block_tree().len(), 4);
+
+        assert_eq!(chain.submit_block(branch[1].clone()).unwrap(), BlockStatus::NewTip { disconnected: 0 });
+        assert!(chain.last_block().unwrap() == branch[1]);
+        assert_eq!(chain.accounts.get_account_amount(&RECEIVER).unwrap(), 2 * chain.params.block_subsidy(2));
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn reorganization_returns_orphaned_transactions_to_the_mempool() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        let mut other = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        other.submit_block(chain.last_block().unwrap()).unwrap();
+
+        chain.sign_transaction(&miner, RECEIVER, 10, 1, None);
+        mine_blocks(&mut chain, 1);
+        assert!(chain.mempool.is_empty());
+        other.mine(RECEIVER, 1).unwrap();
+        other.mine(RECEIVER, 1).unwrap();
+
+        chain.sync_from(&other).unwrap();
+        assert!(chain.last_block().unwrap() == other.last_block().unwrap());
+        assert_eq!(chain.get_block_tree().len(), 1);
+        assert_eq!(chain.mempool.len(), 1);
+        assert_eq!(chain.accounts.get_account_amount(&RECEIVER).unwrap(), 2 * chain.params.block_subsidy(2));
+        assert_eq!(chain.get_total_work(), other.get_total_work());
+    }
+
+    #[test]
+    fn failed_store_leaves_the_chain_at_its_old_tip() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = Chain::with_params(FailingStore::default(), test_params()).unwrap();
+        mine_blocks(&mut chain, 1);
+        let amount = chain.accounts.get_account_amount(&miner.address()).unwrap();
+        let total_work = chain.get_total_work();
+
+        chain.chain.fail_undo = true;
+        assert!(matches!(chain.mine(miner.address(), 1), Err(BlockError::Storage(_))));
+        assert_eq!(chain.chain.len(), 2);
+        assert_eq!(chain.chain.undo_len(), 2);
+        assert_eq!(chain.chain_work.len(), 2);
+        assert_eq!(chain.get_total_work(), total_work);
+        assert_eq!(chain.accounts.get_account_amount(&miner.address()).unwrap(), amount);
+
+        chain.chain.fail_undo = false;
+        mine_blocks(&mut chain, 1);
+        assert_eq!(chain.chain_work.len(), 3);
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn mempool_reports_what_it_drops() {
+        let miner = Keypair::from_private_key(MINER_KEY);
+        let mut chain = test_chain();
+        let first = Transaction::generate_transaction(&miner, RECEIVER, 10, 1, 0, None);
+        let bumped = Transaction::generate_transaction(&miner, RECEIVER, 10, 2, 0, None);
+        assert!(chain.add_transaction(first.clone()).unwrap().is_empty());
+
+        let dropped = chain.add_transaction(bumped.clone()).unwrap();
+        assert!(matches!(dropped.as_slice(), [Dropped { hash, reason: DropReason::Replaced { by } }] if *hash == first.get_hash() && *by == bumped.get_hash()));
+
+        // the replaced transaction comes back with a reorganization but can not be readmitted
+        chain.accounts.clear_all_pending();
+        let dropped = chain.mempool.readmit(vec![first.clone()], &mut chain.accounts, 2);
+        assert!(matches!(dropped.as_slice(), [Dropped { hash, reason: DropReason::Invalid(_) }] if *hash == first.get_hash()));
+        assert!(chain.mempool.get(&bumped.get_hash()).is_some());
+    }
+}
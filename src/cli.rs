@@ -4,6 +4,7 @@ use clap::ArgMatches;
 pub struct Args {
     private_key: u128,
     threads: u8,
+    data_dir: Option<String>,
 }
 
 /// Runs the clap app in order to use cli
@@ -24,6 +25,12 @@ pub fn cli() -> clap::Command {
                 .default_value("8")
                 .help("Number of threads to be used."),
         )
+        .arg(
+            clap::Arg::new("data-dir")
+                .short('d')
+                .long("data-dir")
+                .help("Directory to store the chain in. The chain is kept in memory only if not given."),
+        )
 }
 
 impl Args {
@@ -39,11 +46,14 @@ impl Args {
             .expect("Threads must be a number!");
 
 
+        let data_dir = matches.get_one::<String>("data-dir").cloned();
+
         let private_key = private_key_string.parse::<u128>().expect("private key must be u128 binary format(for now)");
 
         Args {
             private_key,
             threads,
+            data_dir,
         }
     }
 
@@ -54,4 +64,8 @@ impl Args {
     pub fn get_threads(&self) -> u8 {
         self.threads
     }
+
+    pub fn get_data_dir(&self) -> Option<&str> {
+        self.data_dir.as_deref()
+    }
 }
\ No newline at end of file
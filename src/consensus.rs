@@ -0,0 +1,168 @@
+use crate::error::ParamsError;
+use crate::target::{CompactTarget, U256};
+
+/// Microseconds, the unit of block header timestamps.
+pub const SECOND: u128 = 1_000_000;
+
+/// Blocks can not claim a time further than this ahead of the validating node's clock.
+pub const MAX_FUTURE_BLOCK_TIME: u128 = 2 * 60 * 60 * SECOND;
+
+/// A single retarget can make the target at most this many times easier or harder.
+const MAX_RETARGET_FACTOR: u128 = 4;
+
+/// Consensus rules a `Chain` is built with. Every node on a chain has to use the same parameters.
+#[derive(Clone, Debug)]
+pub struct ConsensusParams {
+    /// Timestamp of the genesis block, in microseconds. It is fixed so every node builds the same genesis block.
+    pub genesis_time: u128,
+    /// Target of the genesis block and of every block until the first retarget.
+    pub initial_target: CompactTarget,
+    /// Easiest target retargeting is allowed to reach.
+    pub max_target: CompactTarget,
+    /// Block time the target is adjusted towards, in microseconds. Has to be at least 1.
+    pub target_block_time: u128,
+    /// Target is retargeted every `retarget_interval` blocks. A window needs at least two blocks to measure a block time.
+    pub retarget_interval: usize,
+    /// Block subsidy paid to the miner of the first block.
+    pub initial_subsidy: u128,
+    /// Subsidy is halved every `halving_interval` blocks, which has to be at least 1.
+    pub halving_interval: usize,
+    /// Smallest subsidy once halving has brought it below this amount. Without it the subsidy reaches zero
+    /// and the supply is capped at `max_supply`.
+    pub tail_emission: Option<u128>,
+    /// Largest allowed encoded block, header and coinbase included, in bytes.
+    pub max_block_size: usize,
+    /// Most transactions a block can hold, coinbase included.
+    pub max_block_transactions: usize,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            genesis_time: 1_690_000_000 * SECOND,
+            initial_target: CompactTarget::from_leading_zeros(24),
+            max_target: CompactTarget::from_leading_zeros(1),
+            target_block_time: 10 * SECOND,
+            retarget_interval: 10,
+            initial_subsidy: 50,
+            halving_interval: 210_000,
+            tail_emission: None,
+            max_block_size: 100_000,
+            max_block_transactions: 500,
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// Rejects parameters the consensus rules can not work with.
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        if self.target_block_time == 0 {
+            return Err(ParamsError("target block time must be at least 1 microsecond"));
+        }
+        if self.retarget_interval < 2 {
+            return Err(ParamsError("retarget interval must be at least 2 blocks"));
+        }
+        if self.halving_interval == 0 {
+            return Err(ParamsError("halving interval must be at least 1 block"));
+        }
+        Ok(())
+    }
+
+    pub fn is_retarget_height(&self, height: usize) -> bool {
+        height > 0 && height.is_multiple_of(self.retarget_interval)
+    }
+
+    /// Height of the first block of the window measured by the retarget at `height`, the last block being the one at `height - 1`.
+    /// The first window starts after genesis since the genesis time is fixed and says nothing about how fast blocks are mined.
+    pub fn retarget_window_start(&self, height: usize) -> usize {
+        height.saturating_sub(self.retarget_interval).max(1)
+    }
+
+    /// New coins the miner of the block at `height` is allowed to create, on top of the fees of the block.
+    pub fn block_subsidy(&self, height: usize) -> u128 {
+        let halvings = height.checked_div(self.halving_interval).unwrap_or(0);
+        let subsidy = self.initial_subsidy.checked_shr(halvings as u32).unwrap_or(0);
+        match self.tail_emission {
+            Some(tail_emission) => subsidy.max(tail_emission),
+            None => subsidy,
+        }
+    }
+
+    /// Total amount of coins the schedule will ever create, `None` if there is tail emission.
+    /// The genesis block has no coinbase, so the first halving period pays one subsidy less than the others.
+    pub fn max_supply(&self) -> Option<u128> {
+        if self.tail_emission.is_some() {
+            return None;
+        }
+
+        let mut supply: u128 = 0;
+        let mut subsidy = self.initial_subsidy;
+        while subsidy > 0 {
+            supply = supply.saturating_add(subsidy.saturating_mul(self.halving_interval as u128));
+            subsidy >>= 1;
+        }
+        Some(supply.saturating_sub(self.initial_subsidy))
+    }
+
+    /// Scales the target of a retarget window by how long the window actually took compared to the target block time.
+    /// `actual_span` is the time between the first and the last block of the window, which has `intervals` block intervals.
+    /// A window without intervals leaves the target unchanged.
+    pub fn retarget(&self, target: CompactTarget, intervals: usize, actual_span: u128) -> CompactTarget {
+        if intervals == 0 {
+            return target;
+        }
+        let target_span = self.target_block_time.saturating_mul(intervals as u128).max(1);
+        let actual_span = actual_span.clamp(target_span / MAX_RETARGET_FACTOR, target_span.saturating_mul(MAX_RETARGET_FACTOR)).max(1);
+
+        let max_target = self.max_target.to_target().unwrap_or(U256::MAX);
+        let target = target.to_target().unwrap_or(max_target);
+        let (actual_span, target_span) = (U256::from(actual_span), U256::from(target_span));
+        let new_target = match target.checked_mul(actual_span) {
+            Some(scaled) => scaled / target_span,
+            None => (target / target_span).saturating_mul(actual_span),
+        };
+        CompactTarget::from_target(new_target.min(max_target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_the_subsidy_at_every_interval() {
+        let params = ConsensusParams { initial_subsidy: 50, halving_interval: 10, ..ConsensusParams::default() };
+        for (height, subsidy) in [(1, 50), (9, 50), (10, 25), (19, 25), (20, 12), (50, 1), (59, 1), (60, 0), (10_000, 0)] {
+            assert_eq!(params.block_subsidy(height), subsidy, "height {height}");
+        }
+
+        let tail = ConsensusParams { tail_emission: Some(3), ..params };
+        for (height, subsidy) in [(1, 50), (10, 25), (29, 12), (30, 6), (39, 6), (40, 3), (50, 3), (10_000, 3)] {
+            assert_eq!(tail.block_subsidy(height), subsidy, "height {height}");
+        }
+        assert_eq!(tail.max_supply(), None);
+    }
+
+    #[test]
+    fn first_retarget_window_starts_after_genesis() {
+        let params = ConsensusParams { retarget_interval: 3, ..ConsensusParams::default() };
+        assert_eq!(params.retarget_window_start(3), 1);
+        assert_eq!(params.retarget_window_start(6), 3);
+
+        let target = CompactTarget::from_leading_zeros(40);
+        let easier = params.retarget(target, 2, 4 * params.target_block_time);
+        assert!(easier.to_target().unwrap() > target.to_target().unwrap());
+        assert_eq!(params.retarget(target, 2, 2 * params.target_block_time), target);
+        assert_eq!(params.retarget(target, 0, 0), target);
+    }
+
+    #[test]
+    fn max_supply_is_what_the_coinbases_pay() {
+        for halving_interval in [1, 2, 10] {
+            let params = ConsensusParams { initial_subsidy: 50, halving_interval, ..ConsensusParams::default() };
+            let paid: u128 = (1..=7 * halving_interval).map(|height| params.block_subsidy(height)).sum();
+            assert_eq!(params.block_subsidy(6 * halving_interval), 0);
+            assert_eq!(params.max_supply(), Some(paid));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::DecodeError;
+
+/// Version byte written in front of every encoded value. Decoding rejects any other version.
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Deterministic binary encoding. Integers are written big endian and lists are prefixed with a `u32` length.
+pub trait Encode {
+    fn encode_to(&self, bytes: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![ENCODING_VERSION];
+        self.encode_to(&mut bytes);
+        bytes
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError>;
+
+    /// Decodes a value written by `Encode::to_bytes`, rejecting unknown versions and trailing bytes.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.read_u8()?;
+        if version != ENCODING_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let value = Self::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(value)
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        if self.bytes.len() < N {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().expect("head is N bytes long"))
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        self.read_array().map(u8::from_be_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        self.read_array().map(u32::from_be_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        self.read_array().map(u64::from_be_bytes)
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, DecodeError> {
+        self.read_array().map(u128::from_be_bytes)
+    }
+
+    /// Reads a list length, refusing lengths that could not possibly fit in the remaining bytes.
+    pub fn read_length(&mut self, min_item_length: usize) -> Result<usize, DecodeError> {
+        let length = self.read_u32()? as usize;
+        if length.saturating_mul(min_item_length) > self.remaining() {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        Ok(length)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(DecodeError::TrailingBytes(remaining)),
+        }
+    }
+}
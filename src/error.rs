@@ -1,4 +1,6 @@
 use thiserror::Error;
+use crate::hash::Hash256;
+use crate::target::CompactTarget;
 
 #[derive(Error, Debug)]
 #[error("Account does not exists!")]
@@ -8,10 +10,116 @@ pub struct AccountDNE;
 #[error("Transaction Error: {0}")]
 pub struct TransactionError(pub String);
 
+#[derive(Error, Debug, PartialEq)]
+#[error("Hash Error: {0}")]
+pub struct HashParseError(pub &'static str);
+
 #[derive(Error, Debug)]
 #[error("Miner Error: {0}")]
 pub struct MinerError(pub &'static str);
 
 #[derive(Error, Debug)]
 #[error("Chain Error: {0}")]
-pub struct ChainError(pub &'static str);
\ No newline at end of file
+pub struct ChainError(pub &'static str);
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Consensus Error: {0}")]
+pub struct ParamsError(pub &'static str);
+
+/// Why a block handed to `Chain::submit_block` was not accepted.
+#[derive(Error, Debug)]
+pub enum BlockError {
+    #[error("Block Error: parent block {0} is unknown")]
+    UnknownParent(Hash256),
+    #[error(transparent)]
+    Invalid(#[from] ValidationError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    InvalidParams(#[from] ParamsError),
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid block at height {height}: {reason}")]
+pub struct ValidationError {
+    pub height: usize,
+    pub reason: InvalidBlock,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InvalidBlock {
+    #[error("block could not be read: {0}")]
+    Unreadable(String),
+    #[error("genesis block is malformed")]
+    BadGenesis,
+    #[error("unsupported block version {0}")]
+    BadVersion(u32),
+    #[error("block time is not after the parent block's time or too far in the future")]
+    BadTimestamp,
+    #[error("previous block hash does not match the parent block")]
+    BadPrevHash,
+    #[error("target is {found}, expected {expected}")]
+    BadTarget { expected: CompactTarget, found: CompactTarget },
+    #[error("block hash does not meet the target")]
+    InsufficientWork,
+    #[error("block has {found} transactions, at most {max} are allowed")]
+    TooManyTransactions { max: usize, found: usize },
+    #[error("block is {found} bytes, at most {max} are allowed")]
+    TooLarge { max: usize, found: usize },
+    #[error("merkle root does not match the transactions")]
+    BadMerkleRoot,
+    #[error("transaction {0} hash does not match its contents")]
+    BadTransactionHash(Hash256),
+    #[error("transaction {0} has an invalid signature")]
+    BadSignature(Hash256),
+    #[error("transaction {0} expired before this block")]
+    ExpiredTransaction(Hash256),
+    #[error("first transaction of the block is not a coinbase")]
+    MissingCoinbase,
+    #[error("block has more than one coinbase transaction")]
+    DuplicateCoinbase,
+    #[error("coinbase is for height {found}, expected {expected}")]
+    BadCoinbaseHeight { expected: u64, found: u64 },
+    #[error("miner reward is {found}, at most {allowed} is allowed")]
+    BadReward { allowed: u128, found: u128 },
+    #[error("transaction {0} is sent from an account that does not exist")]
+    UnknownSender(Hash256),
+    #[error("transaction {transaction} has nonce {found}, expected {expected}")]
+    BadNonce { transaction: Hash256, expected: u64, found: u64 },
+    #[error("transaction {0} spends more than its sender's balance")]
+    InsufficientBalance(Hash256),
+    #[error("transaction {0} overflows its receiver's balance")]
+    BalanceOverflow(Hash256),
+    #[error("state root is {found}, the block's transactions produce {expected}")]
+    BadStateRoot { expected: Hash256, found: Hash256 },
+}
+
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Storage Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Storage Error: corrupt block log: {0}")]
+    Corrupt(String),
+    #[error("Storage Error: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("Storage Error: stored chain is invalid: {0}")]
+    InvalidChain(#[from] ValidationError),
+    #[error(transparent)]
+    InvalidParams(#[from] ParamsError),
+}
+
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DecodeError {
+    #[error("Decode Error: unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Decode Error: unexpected end of input")]
+    UnexpectedEnd,
+    #[error("Decode Error: {0} trailing bytes")]
+    TrailingBytes(usize),
+    #[error("Decode Error: length {0} does not fit in the input")]
+    InvalidLength(usize),
+    #[error("Decode Error: invalid {0}")]
+    InvalidField(&'static str),
+}
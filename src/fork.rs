@@ -0,0 +1,69 @@
+use crate::blockchain::Block;
+use crate::hash::Hash256;
+use crate::target::U256;
+use std::collections::HashMap;
+
+/// Valid blocks that are not on the main chain, indexed by hash and linked to their parents through `pre_hash`.
+///
+/// They are kept so a branch can be adopted once it has more work than the main chain.
+/// Side blocks only live in memory, the `BlockStore` holds the main chain.
+#[derive(Default)]
+pub struct BlockTree {
+    blocks: HashMap<Hash256, SideBlock>,
+}
+
+pub struct SideBlock {
+    pub block: Block,
+    pub height: usize,
+    /// Work of the branch from genesis up to and including this block.
+    pub chain_work: U256,
+}
+
+/// What happened to a block handed to `Chain::submit_block`.
+#[derive(PartialEq, Debug)]
+pub enum BlockStatus {
+    /// The block is already on the main chain or a side branch.
+    AlreadyKnown,
+    /// The block is on a branch with no more work than the main chain.
+    SideBranch,
+    /// The block is the new tip of the main chain, after disconnecting `disconnected` blocks of the old one.
+    NewTip { disconnected: usize },
+}
+
+impl BlockTree {
+    pub fn insert(&mut self, block: Block, height: usize, chain_work: U256) {
+        self.blocks.insert(block.get_hash(), SideBlock { block, height, chain_work });
+    }
+
+    pub fn get(&self, hash: &Hash256) -> Option<&SideBlock> {
+        self.blocks.get(hash)
+    }
+
+    pub fn remove(&mut self, hash: &Hash256) -> Option<SideBlock> {
+        self.blocks.remove(hash)
+    }
+
+    pub fn contains(&self, hash: &Hash256) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Hashes of the side blocks from the fork point up to `tip`, oldest first, and the hash of the block the branch forks from.
+    pub fn branch(&self, tip: &Hash256) -> (Vec<Hash256>, Hash256) {
+        let mut branch = Vec::new();
+        let mut hash = *tip;
+        while let Some(side_block) = self.blocks.get(&hash) {
+            branch.push(hash);
+            hash = side_block.block.get_prev_hash();
+        }
+        branch.reverse();
+        (branch, hash)
+    }
+}
@@ -0,0 +1,84 @@
+use sha2::{Sha256, Digest};
+use crate::accounts::Transaction;
+use crate::blockchain::BlockHeader;
+use crate::encoding::Encode;
+use crate::error::HashParseError;
+use std::fmt;
+use std::str::FromStr;
+
+pub const HASH_LENGTH: usize = 32;
+
+/// First byte hashed into a merkle leaf, so a leaf can never be mistaken for an inner node.
+const MERKLE_LEAF_TAG: u8 = 0;
+/// First byte hashed into an inner merkle node.
+const MERKLE_NODE_TAG: u8 = 1;
+
+/// Full SHA-256 hash, used for block hashes, merkle roots, state roots and transaction ids.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, Default)]
+pub struct Hash256([u8; HASH_LENGTH]);
+
+impl Hash256 {
+    pub const ZERO: Hash256 = Hash256([0; HASH_LENGTH]);
+
+    pub fn from_bytes(bytes: [u8; HASH_LENGTH]) -> Self {
+        Hash256(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_LENGTH] {
+        &self.0
+    }
+
+    pub fn digest(input: &[u8]) -> Self {
+        Hash256(Sha256::digest(input).into())
+    }
+
+    /// Block id, the digest of the encoded header. The proof of work is checked against it.
+    pub fn block_hash(header: &BlockHeader) -> Self {
+        Hash256::digest(&header.to_bytes())
+    }
+
+    /// Transaction id, the digest of the encoded transaction with its signature. These are the leaves of a block's merkle tree.
+    pub fn transaction_hash(transaction: &Transaction) -> Self {
+        Hash256::digest(&transaction.to_bytes())
+    }
+
+    /// Parent of the `left` and `right` nodes of a merkle tree, the digest of the node tag and both hashes.
+    pub fn merkle_node(left: &Hash256, right: &Hash256) -> Self {
+        let mut input = [MERKLE_NODE_TAG; 1 + 2 * HASH_LENGTH];
+        input[1..1 + HASH_LENGTH].copy_from_slice(&left.0);
+        input[1 + HASH_LENGTH..].copy_from_slice(&right.0);
+        Hash256::digest(&input)
+    }
+
+    /// Leaf of a block's merkle tree for the transaction with id `transaction_hash`.
+    pub fn merkle_leaf(transaction_hash: &Hash256) -> Self {
+        let mut input = [MERKLE_LEAF_TAG; 1 + HASH_LENGTH];
+        input[1..].copy_from_slice(&transaction_hash.0);
+        Hash256::digest(&input)
+    }
+
+    /// Hashes the confirmed state of an account into a leaf of the state tree.
+    pub fn state_leaf(address: u128, amount: u128, nonce: u64) -> Self {
+        let mut input = [MERKLE_LEAF_TAG; 1 + 16 + 16 + 8];
+        input[1..17].copy_from_slice(&address.to_be_bytes());
+        input[17..33].copy_from_slice(&amount.to_be_bytes());
+        input[33..].copy_from_slice(&nonce.to_be_bytes());
+        Hash256::digest(&input)
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = HashParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; HASH_LENGTH];
+        hex::decode_to_slice(s, &mut bytes).map_err(|_| HashParseError("expected 64 hex characters"))?;
+        Ok(Hash256(bytes))
+    }
+}
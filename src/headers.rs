@@ -0,0 +1,218 @@
+use crate::blockchain::{BlockHeader, Chain};
+use crate::consensus::ConsensusParams;
+use crate::error::{BlockError, InvalidBlock, ValidationError};
+use crate::fork::BlockStatus;
+use crate::hash::Hash256;
+use crate::merkle::{self, MerkleProof};
+use crate::state::StateProof;
+use crate::storage::BlockStore;
+use crate::target::{CompactTarget, U256};
+use std::collections::HashMap;
+
+/// Main chain of block headers without the transactions, for light clients.
+///
+/// Headers are checked for linkage, timestamps, targets and proof of work, which is all that can be checked without
+/// the transactions. Transactions and account states are then verified with merkle proofs against the headers, trusting
+/// the chain with the most work to only contain valid blocks.
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    heights: HashMap<Hash256, usize>,
+    /// Total work of the header chain up to every height.
+    chain_work: Vec<U256>,
+    params: ConsensusParams,
+}
+
+impl HeaderChain {
+    /// Starts a header chain from the genesis header of a chain built with `params`.
+    pub fn new(genesis: BlockHeader, params: ConsensusParams) -> Result<Self, BlockError> {
+        params.validate()?;
+        if !genesis.is_genesis(&params) {
+            return Err(ValidationError { height: 0, reason: InvalidBlock::BadGenesis }.into());
+        }
+
+        Ok(HeaderChain {
+            heights: HashMap::from([(genesis.get_hash(), 0)]),
+            chain_work: vec![genesis.get_work()],
+            headers: vec![genesis],
+            params,
+        })
+    }
+
+    /// Adds consecutive headers, the first of which extends a known header. Headers already on the chain are skipped.
+    /// If the new branch has more work than the current chain it becomes the chain, otherwise it is dropped
+    /// since only the main chain is kept.
+    pub fn submit_headers(&mut self, headers: &[BlockHeader]) -> Result<BlockStatus, BlockError> {
+        let known = headers.iter().take_while(|header| self.heights.contains_key(&header.get_hash())).count();
+        let Some(first) = headers.get(known) else {
+            return Ok(BlockStatus::AlreadyKnown);
+        };
+        let fork_height = *self.heights.get(&first.get_prev_hash()).ok_or(BlockError::UnknownParent(first.get_prev_hash()))?;
+
+        let mut branch: Vec<BlockHeader> = Vec::new();
+        let mut chain_work = self.chain_work[fork_height];
+        for (height, header) in (fork_height + 1..).zip(&headers[known..]) {
+            let prev_header = branch.last().unwrap_or(&self.headers[fork_height]);
+            let target = self.expected_target(height, prev_header, fork_height, &branch);
+            header.validate(prev_header, target).map_err(|reason| ValidationError { height, reason })?;
+            chain_work = chain_work.saturating_add(header.get_work());
+            branch.push(header.clone());
+        }
+
+        // on equal work the chain seen first is kept
+        if chain_work <= self.get_total_work() {
+            return Ok(BlockStatus::SideBranch);
+        }
+        let disconnected = self.headers.len() - fork_height - 1;
+        for header in self.headers.drain(fork_height + 1..) {
+            self.heights.remove(&header.get_hash());
+        }
+        self.chain_work.truncate(fork_height + 1);
+        for header in branch {
+            let chain_work = self.get_total_work().saturating_add(header.get_work());
+            self.heights.insert(header.get_hash(), self.headers.len());
+            self.chain_work.push(chain_work);
+            self.headers.push(header);
+        }
+        Ok(BlockStatus::NewTip { disconnected })
+    }
+
+    /// Submits the headers of a full chain above the last header both chains share.
+    pub fn sync_from<S: BlockStore>(&mut self, chain: &Chain<S>) -> Result<BlockStatus, BlockError> {
+        let common = self.headers.iter()
+            .rposition(|header| chain.get_store().height_of(&header.get_hash()).is_some())
+            .ok_or(BlockError::UnknownParent(self.headers[0].get_hash()))?;
+        let headers = chain.get_headers(chain.get_store().height_of(&self.headers[common].get_hash()).expect("common header is on the chain") + 1)?;
+        self.submit_headers(&headers)
+    }
+
+    /// Whether `proof` shows the transaction `transaction_hash` is in the block `block_hash` of the header chain.
+    pub fn verify_transaction(&self, block_hash: &Hash256, transaction_hash: &Hash256, proof: &MerkleProof) -> bool {
+        self.get_by_hash(block_hash).is_some_and(|header| merkle::verify(&header.get_merkle_root(), transaction_hash, proof))
+    }
+
+    /// Whether `proof` shows the account at `address` had `account`, its `(amount, nonce)`, after the block `block_hash`
+    /// of the header chain, or did not exist if it is `None`.
+    pub fn verify_account(&self, block_hash: &Hash256, address: u128, account: Option<(u128, u64)>, proof: &StateProof) -> bool {
+        self.get_by_hash(block_hash).is_some_and(|header| proof.verify(&header.get_state_root(), address, account))
+    }
+
+    /// Number of headers from the block `block_hash` up to the tip, counting both. `None` if the block is not on the chain.
+    pub fn confirmations(&self, block_hash: &Hash256) -> Option<usize> {
+        self.heights.get(block_hash).map(|height| self.headers.len() - height)
+    }
+
+    pub fn get(&self, height: usize) -> Option<&BlockHeader> {
+        self.headers.get(height)
+    }
+
+    pub fn get_by_hash(&self, hash: &Hash256) -> Option<&BlockHeader> {
+        self.heights.get(hash).map(|height| &self.headers[*height])
+    }
+
+    pub fn height_of(&self, hash: &Hash256) -> Option<usize> {
+        self.heights.get(hash).copied()
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().expect("header chain always has a genesis header")
+    }
+
+    /// Height of the tip, genesis is at 0.
+    pub fn get_height(&self) -> usize {
+        self.headers.len() - 1
+    }
+
+    /// Sum of the work of every header in the chain.
+    pub fn get_total_work(&self) -> U256 {
+        *self.chain_work.last().expect("header chain always has a genesis header")
+    }
+
+    /// Target the header at `height` has to meet, on the branch made of the chain up to `fork_height` followed by `branch`.
+    fn expected_target(&self, height: usize, prev_header: &BlockHeader, fork_height: usize, branch: &[BlockHeader]) -> CompactTarget {
+        if !self.params.is_retarget_height(height) {
+            return prev_header.get_target();
+        }
+
+        let first_height = self.params.retarget_window_start(height);
+        let first_header = match first_height.checked_sub(fork_height + 1) {
+            Some(index) => &branch[index],
+            None => &self.headers[first_height],
+        };
+        let actual_span = prev_header.get_time().saturating_sub(first_header.get_time());
+        self.params.retarget(prev_header.get_target(), height - 1 - first_height, actual_span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Keypair;
+    use crate::storage::MemoryStore;
+    use crate::test_utils::{mine_blocks, test_params, MINER_KEY, RECEIVER};
+
+    #[test]
+    fn follows_the_chain_and_verifies_proofs() {
+        let mut chain = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        mine_blocks(&mut chain, 2);
+        let miner = Keypair::from_private_key(MINER_KEY);
+        chain.sign_transaction(&miner, RECEIVER, 10, 1, None);
+        mine_blocks(&mut chain, 1);
+
+        let mut headers = HeaderChain::new(chain.get_headers(0).unwrap().remove(0), test_params()).unwrap();
+        assert_eq!(headers.sync_from(&chain).unwrap(), BlockStatus::NewTip { disconnected: 0 });
+        assert_eq!(headers.get_height(), 3);
+        assert_eq!(headers.get_total_work(), chain.get_total_work());
+        assert_eq!(headers.sync_from(&chain).unwrap(), BlockStatus::AlreadyKnown);
+
+        let block = chain.get_store().tip().unwrap().unwrap();
+        let transaction = &block.get_transactions()[1];
+        let proof = block.prove_transaction(1).unwrap();
+        assert!(headers.verify_transaction(&block.get_hash(), &transaction.get_hash(), &proof));
+        assert!(!headers.verify_transaction(&block.get_prev_hash(), &transaction.get_hash(), &proof));
+        assert_eq!(headers.confirmations(&block.get_hash()), Some(1));
+
+        let account_proof = chain.get_accounts().prove_account(&RECEIVER);
+        assert!(headers.verify_account(&block.get_hash(), RECEIVER, Some((10, 0)), &account_proof));
+        assert!(!headers.verify_account(&block.get_hash(), RECEIVER, None, &account_proof));
+    }
+
+    #[test]
+    fn rejects_headers_that_do_not_link_or_meet_the_target() {
+        let mut chain = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        mine_blocks(&mut chain, 3);
+        let all_headers = chain.get_headers(0).unwrap();
+        let mut headers = HeaderChain::new(all_headers[0].clone(), test_params()).unwrap();
+
+        assert!(matches!(headers.submit_headers(&all_headers[2..]), Err(BlockError::UnknownParent(_))));
+
+        let mut harder_params = test_params();
+        harder_params.initial_target = CompactTarget::from_leading_zeros(40);
+        assert!(HeaderChain::new(all_headers[0].clone(), harder_params).is_err());
+
+        let mut skipped = vec![all_headers[1].clone(), all_headers[3].clone()];
+        assert!(matches!(
+            headers.submit_headers(&skipped),
+            Err(BlockError::Invalid(ValidationError { height: 2, reason: InvalidBlock::BadPrevHash }))
+        ));
+        assert_eq!(headers.get_height(), 0);
+
+        skipped.truncate(1);
+        headers.submit_headers(&skipped).unwrap();
+        assert_eq!(headers.get_height(), 1);
+    }
+
+    #[test]
+    fn switches_to_the_branch_with_more_work() {
+        let mut chain = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        let mut fork = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+        mine_blocks(&mut chain, 2);
+        mine_blocks(&mut fork, 3);
+
+        let mut headers = HeaderChain::new(chain.get_headers(0).unwrap().remove(0), test_params()).unwrap();
+        headers.sync_from(&chain).unwrap();
+        assert_eq!(headers.sync_from(&fork).unwrap(), BlockStatus::NewTip { disconnected: 2 });
+        assert_eq!(headers.tip().get_hash(), fork.get_headers(3).unwrap()[0].get_hash());
+        assert_eq!(headers.sync_from(&chain).unwrap(), BlockStatus::SideBranch);
+        assert_eq!(headers.get_height(), 3);
+    }
+}
@@ -0,0 +1,86 @@
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use sha2::{Sha256, Digest};
+use crate::error::TransactionError;
+use crate::hash::Hash256;
+use std::fmt;
+
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// Ed25519 key pair. The signing key is derived from the `u128` private key the cli works with.
+pub struct Keypair {
+    signing_key: SigningKey,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub struct PublicKey([u8; PUBLIC_KEY_LENGTH]);
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Signature([u8; SIGNATURE_LENGTH]);
+
+impl Keypair {
+    pub fn from_private_key(private_key: u128) -> Self {
+        let seed: [u8; 32] = Sha256::digest(private_key.to_be_bytes()).into();
+        Keypair {
+            signing_key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn address(&self) -> u128 {
+        self.public_key().address()
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature(self.signing_key.sign(message).to_bytes())
+    }
+}
+
+impl PublicKey {
+    pub fn from_bytes(bytes: [u8; PUBLIC_KEY_LENGTH]) -> Self {
+        PublicKey(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.0
+    }
+
+    /// Addresses are the first 16 bytes of the public key's hash.
+    pub fn address(&self) -> u128 {
+        let hash = Hash256::digest(&self.0);
+        u128::from_be_bytes(hash.as_bytes()[..16].try_into().unwrap())
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), TransactionError> {
+        let verifying_key = VerifyingKey::from_bytes(&self.0)
+            .map_err(|_| TransactionError("Invalid public key".to_string()))?;
+        let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+        verifying_key.verify_strict(message, &signature)
+            .map_err(|_| TransactionError("Invalid signature".to_string()))
+    }
+}
+
+impl Signature {
+    pub fn from_bytes(bytes: [u8; SIGNATURE_LENGTH]) -> Self {
+        Signature(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SIGNATURE_LENGTH] {
+        &self.0
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
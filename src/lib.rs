@@ -1,6 +1,18 @@
 pub mod blockchain;
+pub mod fork;
+pub mod headers;
+pub mod consensus;
+pub mod target;
 pub mod accounts;
-pub mod sha128;
+pub mod state;
+pub mod merkle;
+pub mod mempool;
+pub mod hash;
+pub mod keys;
 pub mod error;
 pub mod cli;
-pub mod miner;
\ No newline at end of file
+pub mod miner;
+pub mod storage;
+pub mod encoding;
+#[cfg(test)]
+mod test_utils;
\ No newline at end of file
@@ -2,7 +2,8 @@ use std::io::{self, Write};
 
 use rustychain::cli::{cli, Args};
 use rustychain::miner::Miner;
-use rustychain::sha128::Sha128;
+use rustychain::storage::BlockStore;
+use rustychain::keys::Keypair;
 
 const COMMAND_STRING: &str = "Type command\n\
             :m => to mine\n\
@@ -10,7 +11,9 @@ const COMMAND_STRING: &str = "Type command\n\
             :pa => to print all accounts and their balances\n\
             :s => to send a transaction from the miners private key\n\
             :ss => to send a transaction with a new private key\n\
+            :r => to replace a pending transaction of the miner with a higher fee\n\
             :pta => see the address of given private key [for testing]\n\
+            :v => to validate the whole chain\n\
             :q => to quit\n";
 
 const INCORRECT_COMMAND_STRING: &str = "Please check menu and type one of the correct commands. <ex type ':h' to see commands>";
@@ -40,12 +43,25 @@ fn u128_input() -> Option<u128> {
     }
 }
 
+/// Reads an expiry height, where 0 means the transaction never expires. `None` if the user quits.
+fn expiry_input() -> Option<Option<u64>> {
+    println!("Enter expiry height, 0 for none");
+    u128_input().map(|height| Some(u64::try_from(height).unwrap_or(u64::MAX)).filter(|height| *height != 0))
+}
+
 
 fn main() {
     let cli = cli();
     let args = Args::get_args(cli.get_matches());
 
-    let mut miner = Miner::new(args.get_private_key(), args.get_threads());
+    match args.get_data_dir() {
+        Some(data_dir) => run(Miner::open(args.get_private_key(), args.get_threads(), data_dir)
+            .expect("Failed to load the stored chain")),
+        None => run(Miner::new(args.get_private_key(), args.get_threads())),
+    }
+}
+
+fn run<S: BlockStore>(mut miner: Miner<S>) {
     println!("{}",COMMAND_STRING);
 
     let mut command;
@@ -61,8 +77,11 @@ fn main() {
                 let receiver_address = u128_input();
                 println!("Enter amount");
                 let amount = u128_input();
-                match (receiver_address, amount) {
-                    (Some(r_addr), Some(a)) => miner.send_transaction(r_addr, a),
+                println!("Enter fee");
+                let fee = u128_input();
+                let expiry = expiry_input();
+                match (receiver_address, amount, fee, expiry) {
+                    (Some(r_addr), Some(a), Some(f), Some(e)) => miner.send_transaction(r_addr, a, f, e),
                     _ => println!("Aborting."),
                 }
             },
@@ -73,8 +92,26 @@ fn main() {
                 let receiver_address = u128_input();
                 println!("Enter amount");
                 let amount = u128_input();
-                match (sender_private_key, receiver_address, amount) {
-                    (Some(sender_pk), Some(r_addr), Some(a)) => miner.send_transaction_with_another_private_key(sender_pk, r_addr, a),
+                println!("Enter fee");
+                let fee = u128_input();
+                let expiry = expiry_input();
+                match (sender_private_key, receiver_address, amount, fee, expiry) {
+                    (Some(sender_pk), Some(r_addr), Some(a), Some(f), Some(e)) => miner.send_transaction_with_another_private_key(sender_pk, r_addr, a, f, e),
+                    _ => println!("Aborting."),
+                }
+            },
+            ":r" | "r" => {
+                println!("Enter nonce of the pending transaction");
+                let nonce = u128_input().and_then(|nonce| u64::try_from(nonce).ok());
+                println!("Enter receiver address");
+                let receiver_address = u128_input();
+                println!("Enter amount");
+                let amount = u128_input();
+                println!("Enter fee");
+                let fee = u128_input();
+                let expiry = expiry_input();
+                match (nonce, receiver_address, amount, fee, expiry) {
+                    (Some(n), Some(r_addr), Some(a), Some(f), Some(e)) => miner.replace_transaction(n, r_addr, a, f, e),
                     _ => println!("Aborting."),
                 }
             },
@@ -83,10 +120,14 @@ fn main() {
                 println!("Enter sender private key");
                 let private_key = u128_input();
                 match private_key {
-                    Some(pk) => println!("Public key: {}\nPay Address: {}", Sha128::public_key(pk), Sha128::address(pk)),
+                    Some(pk) => {
+                        let keypair = Keypair::from_private_key(pk);
+                        println!("Public key: {}\nPay Address: {}", keypair.public_key(), keypair.address())
+                    },
                     _ => println!("Aborting."),
                 }
             }
+            ":v" | "v" => miner.validate_chain(),
             ":h" | "h" => println!("{}", COMMAND_STRING),
             _ => println!("{}", INCORRECT_COMMAND_STRING),
         }
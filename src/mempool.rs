@@ -0,0 +1,442 @@
+use crate::accounts::{Accounts, Transaction};
+use crate::encoding::Encode;
+use crate::error::TransactionError;
+use crate::hash::Hash256;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+
+/// Default limit on the total encoded size of the pending transactions, in bytes.
+pub const DEFAULT_MAX_POOL_SIZE: usize = 1_000_000;
+
+/// A transaction that left the pool without being mined.
+#[derive(Debug)]
+pub struct Dropped {
+    pub hash: Hash256,
+    pub reason: DropReason,
+}
+
+#[derive(Debug)]
+pub enum DropReason {
+    /// Evicted to make room for a transaction paying a higher fee rate.
+    Evicted,
+    /// Replaced by a transaction with the same sender and nonce paying a higher fee.
+    Replaced { by: Hash256 },
+    /// Not mined by its expiry height.
+    Expired,
+    /// No longer valid against the confirmed balances and nonces.
+    Invalid(TransactionError),
+    /// An earlier transaction of the same sender was dropped, so its nonce can not be mined anymore.
+    NonceGap,
+}
+
+/// Transactions waiting to be mined.
+///
+/// Every transaction in the pool has been admitted against `Accounts`, so its amount and fee stay reserved
+/// from the sender's balance until it is mined or dropped from the pool.
+pub struct Mempool {
+    transactions: HashMap<Hash256, Transaction>,
+    /// Hashes of every sender's transactions by nonce. The nonces of a sender are always consecutive.
+    by_sender: HashMap<u128, BTreeMap<u64, Hash256>>,
+    size: usize,
+    max_size: usize,
+    /// Height of the block the pool is building a template for.
+    height: u64,
+}
+
+impl Mempool {
+    pub fn new(max_size: usize) -> Self {
+        Mempool {
+            transactions: HashMap::new(),
+            by_sender: HashMap::new(),
+            size: 0,
+            max_size,
+            height: 0,
+        }
+    }
+
+    /// Validates a transaction and adds it to the pool. When the pool is full the transactions with the lowest fee rate
+    /// are evicted to make room, but only if they all pay a lower fee rate than the new transaction.
+    ///
+    /// A transaction with the same sender and nonce as a pending one replaces it if it pays a higher fee,
+    /// which lets a sender bump a stuck transaction or cancel it by sending the amount back to themselves.
+    /// Returns the transactions that were evicted or replaced.
+    pub fn insert(&mut self, transaction: Transaction, accounts: &mut Accounts) -> Result<Vec<Dropped>, TransactionError> {
+        if self.transactions.contains_key(&transaction.get_hash()) {
+            return Err(TransactionError("Transaction is already pending!".to_string()));
+        }
+        if transaction.is_expired_at(self.height) {
+            let err_str = format!("Transaction expired! expiry: `{}`, next block: `{}`", transaction.get_expiry().unwrap_or_default(), self.height);
+            return Err(TransactionError(err_str));
+        }
+        let replaced = self.get_pending(&transaction).cloned();
+        match &replaced {
+            Some(replaced) => {
+                if transaction.get_fee() <= replaced.get_fee() {
+                    let err_str = format!("Replacement fee too low! pending fee: `{}`, got: `{}`", replaced.get_fee(), transaction.get_fee());
+                    return Err(TransactionError(err_str));
+                }
+                accounts.check_replacement(&transaction, replaced)?;
+            },
+            None => accounts.check_transaction(&transaction)?,
+        }
+
+        let sender_address = transaction.get_sender_address().expect("checked transactions have a sender");
+        let size = encoded_size(&transaction);
+        if size > self.max_size {
+            return Err(TransactionError("Transaction is larger than the mempool!".to_string()));
+        }
+        let freed = replaced.as_ref().map_or(0, encoded_size);
+        let needed = (self.size + size).saturating_sub(self.max_size + freed);
+        let evicted = self.eviction_candidates(needed, sender_address, fee_rate(&transaction))
+            .ok_or(TransactionError("Mempool is full! Pay a higher fee rate to replace pending transactions.".to_string()))?;
+        let mut dropped = Vec::new();
+        for hash in evicted {
+            if let Some(evicted) = self.remove(&hash) {
+                accounts.release_transaction(&evicted);
+                dropped.push(Dropped { hash, reason: DropReason::Evicted });
+            }
+        }
+
+        match replaced {
+            Some(replaced) => {
+                self.remove(&replaced.get_hash());
+                accounts.replace_reservation(&replaced, &transaction);
+                dropped.push(Dropped { hash: replaced.get_hash(), reason: DropReason::Replaced { by: transaction.get_hash() } });
+            },
+            None => accounts.reserve_transaction(&transaction),
+        }
+        self.add(transaction);
+        Ok(dropped)
+    }
+
+    /// Drops the transactions of a newly applied block and everything that became invalid with it.
+    /// The remaining transactions of every sender in the block are admitted again in nonce order against the updated balances,
+    /// stopping at the first one that no longer fits. Returns the transactions dropped that way.
+    pub fn remove_confirmed(&mut self, block_transactions: &[Transaction], accounts: &mut Accounts) -> Vec<Dropped> {
+        let mut dropped = Vec::new();
+        let senders: HashSet<u128> = block_transactions.iter().filter_map(Transaction::get_sender_address).collect();
+        for sender_address in senders {
+            let pending = self.remove_sender(sender_address);
+            accounts.clear_pending(sender_address);
+
+            let confirmed_nonce = accounts.get_account_nonce(&sender_address).unwrap_or(0);
+            let mut pending = pending.into_iter().filter(|transaction| transaction.get_nonce() >= Some(confirmed_nonce));
+            for transaction in pending.by_ref() {
+                if let Err(err) = accounts.admit_transaction(&transaction) {
+                    dropped.push(Dropped { hash: transaction.get_hash(), reason: DropReason::Invalid(err) });
+                    break;
+                }
+                self.add(transaction);
+            }
+            dropped.extend(pending.map(|transaction| Dropped { hash: transaction.get_hash(), reason: DropReason::NonceGap }));
+        }
+        dropped
+    }
+
+    /// Admits the pending transactions again together with the transactions of blocks disconnected by a reorganization,
+    /// against `accounts` rebuilt for the new main chain without any reservations. Transactions already mined on the new chain
+    /// fail on their nonce and are dropped, as is anything else that is no longer valid. Returns every dropped transaction.
+    pub fn readmit(&mut self, disconnected: Vec<Transaction>, accounts: &mut Accounts, height: u64) -> Vec<Dropped> {
+        let mut transactions: Vec<Transaction> = self.transactions.drain()
+            .map(|(_, transaction)| transaction)
+            .chain(disconnected.into_iter().filter(|transaction| !transaction.is_coinbase()))
+            .collect();
+        self.by_sender.clear();
+        self.size = 0;
+        self.height = height;
+
+        // nonce order per sender, with the highest fee first when a disconnected transaction was replaced in the pool
+        transactions.sort_by_key(|transaction| (transaction.get_sender_address(), transaction.get_nonce(), Reverse(transaction.get_fee())));
+        let mut dropped = Vec::new();
+        for transaction in transactions {
+            let hash = transaction.get_hash();
+            match self.insert(transaction, accounts) {
+                Ok(replaced) => dropped.extend(replaced),
+                Err(err) => dropped.push(Dropped { hash, reason: DropReason::Invalid(err) }),
+            }
+        }
+        dropped
+    }
+
+    /// Moves the pool on to mining the block at `height`, dropping the transactions that expired before it.
+    /// Later transactions of the same sender are dropped too since their nonces can no longer be mined,
+    /// and the balances reserved by everything dropped are released in `accounts`. Returns the dropped transactions.
+    pub fn advance_to(&mut self, height: u64, accounts: &mut Accounts) -> Vec<Dropped> {
+        self.height = height;
+
+        let mut dropped = Vec::new();
+        for nonces in self.by_sender.values() {
+            let mut hashes = nonces.values().skip_while(|hash| !self.transactions[*hash].is_expired_at(height));
+            if let Some(hash) = hashes.next() {
+                dropped.push(Dropped { hash: *hash, reason: DropReason::Expired });
+                dropped.extend(hashes.map(|hash| Dropped { hash: *hash, reason: DropReason::NonceGap }));
+            }
+        }
+        for Dropped { hash, .. } in &dropped {
+            if let Some(transaction) = self.remove(hash) {
+                accounts.release_transaction(&transaction);
+            }
+        }
+        dropped
+    }
+
+    /// Pending transactions ordered by fee rate, highest first, up to `max_size` encoded bytes and `max_transactions` transactions.
+    /// A sender's transactions are always in nonce order, so a transaction only competes on fee rate once the one before it has been picked.
+    /// Transactions that do not fit are skipped along with the rest of their sender's transactions and stay in the pool.
+    pub fn block_template(&self, max_size: usize, max_transactions: usize) -> Vec<Transaction> {
+        let mut queues: HashMap<u128, _> = self.by_sender.iter()
+            .map(|(sender_address, nonces)| (*sender_address, nonces.values()))
+            .collect();
+        let mut heap: BinaryHeap<_> = queues.values_mut()
+            .filter_map(|queue| queue.next())
+            .map(|hash| self.priority(hash))
+            .collect();
+
+        let mut template = Vec::new();
+        let mut size = 0;
+        while let Some((_, Reverse(hash))) = heap.pop() {
+            if template.len() == max_transactions {
+                break;
+            }
+            let transaction = &self.transactions[&hash];
+            let transaction_size = encoded_size(transaction);
+            if size + transaction_size > max_size {
+                continue;
+            }
+
+            let sender_address = transaction.get_sender_address().expect("pending transactions have a sender");
+            if let Some(next) = queues.get_mut(&sender_address).and_then(|queue| queue.next()) {
+                heap.push(self.priority(next));
+            }
+            size += transaction_size;
+            template.push(transaction.clone());
+        }
+        template
+    }
+
+    pub fn get(&self, hash: &Hash256) -> Option<&Transaction> {
+        self.transactions.get(hash)
+    }
+
+    /// The pending transaction with the same sender and nonce as `transaction`.
+    pub fn get_pending(&self, transaction: &Transaction) -> Option<&Transaction> {
+        let nonces = self.by_sender.get(&transaction.get_sender_address()?)?;
+        nonces.get(&transaction.get_nonce()?).and_then(|hash| self.transactions.get(hash))
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Total encoded size of the pending transactions, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Picks the cheapest transactions to free `needed` bytes. A sender's last transaction is always evicted first so their
+    /// remaining nonces stay consecutive, and `protected_sender` is never evicted since the new transaction builds on their nonces.
+    /// `None` if that would evict a transaction paying at least `fee_rate`.
+    fn eviction_candidates(&self, needed: usize, protected_sender: u128, fee_rate: u128) -> Option<Vec<Hash256>> {
+        let mut queues: HashMap<u128, Vec<Hash256>> = self.by_sender.iter()
+            .filter(|(sender_address, _)| **sender_address != protected_sender)
+            .map(|(sender_address, nonces)| (*sender_address, nonces.values().copied().collect()))
+            .collect();
+
+        let mut evicted = Vec::new();
+        let mut freed = 0;
+        while freed < needed {
+            let (sender_address, hash) = queues.iter()
+                .filter_map(|(sender_address, hashes)| hashes.last().map(|hash| (*sender_address, *hash)))
+                .min_by_key(|(_, hash)| self.priority(hash))?;
+            let transaction = &self.transactions[&hash];
+            if self::fee_rate(transaction) >= fee_rate {
+                return None;
+            }
+            queues.get_mut(&sender_address).and_then(Vec::pop);
+            freed += encoded_size(transaction);
+            evicted.push(hash);
+        }
+        Some(evicted)
+    }
+
+    fn add(&mut self, transaction: Transaction) {
+        let sender_address = transaction.get_sender_address().expect("pending transactions have a sender");
+        let nonce = transaction.get_nonce().expect("pending transactions have a nonce");
+        self.by_sender.entry(sender_address).or_default().insert(nonce, transaction.get_hash());
+        self.size += encoded_size(&transaction);
+        self.transactions.insert(transaction.get_hash(), transaction);
+    }
+
+    fn remove(&mut self, hash: &Hash256) -> Option<Transaction> {
+        let transaction = self.transactions.remove(hash)?;
+        let sender_address = transaction.get_sender_address().expect("pending transactions have a sender");
+        if let Some(nonces) = self.by_sender.get_mut(&sender_address) {
+            nonces.retain(|_, pending_hash| pending_hash != hash);
+            if nonces.is_empty() {
+                self.by_sender.remove(&sender_address);
+            }
+        }
+        self.size -= encoded_size(&transaction);
+        Some(transaction)
+    }
+
+    /// Removes all transactions of a sender, returned in nonce order.
+    fn remove_sender(&mut self, sender_address: u128) -> Vec<Transaction> {
+        let nonces = self.by_sender.remove(&sender_address).unwrap_or_default();
+        nonces.values()
+            .filter_map(|hash| self.transactions.remove(hash))
+            .inspect(|transaction| self.size -= encoded_size(transaction))
+            .collect()
+    }
+
+    /// Orders transactions by fee rate, breaking ties by hash so templates are deterministic.
+    fn priority(&self, hash: &Hash256) -> (u128, Reverse<Hash256>) {
+        (fee_rate(&self.transactions[hash]), Reverse(*hash))
+    }
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            DropReason::Evicted => write!(f, "Evicting transaction {} from the mempool", self.hash),
+            DropReason::Replaced { by } => write!(f, "Replacing transaction {} with {}", self.hash, by),
+            DropReason::Expired => write!(f, "Dropping transaction {}: expired", self.hash),
+            DropReason::Invalid(err) => write!(f, "Dropping transaction {}: {}", self.hash, err),
+            DropReason::NonceGap => write!(f, "Dropping transaction {}: an earlier transaction of its sender was dropped", self.hash),
+        }
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(DEFAULT_MAX_POOL_SIZE)
+    }
+}
+
+fn encoded_size(transaction: &Transaction) -> usize {
+    transaction.to_bytes().len()
+}
+
+/// Fee paid per thousand encoded bytes.
+fn fee_rate(transaction: &Transaction) -> u128 {
+    transaction.get_fee().saturating_mul(1000) / encoded_size(transaction) as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Keypair;
+    use crate::test_utils::RECEIVER;
+
+    const BALANCE: u128 = 100;
+
+    /// Accounts where every key in `keys` has a confirmed balance of `BALANCE`.
+    fn funded_accounts(keys: &[u128]) -> (Accounts, Vec<Keypair>) {
+        let keypairs: Vec<Keypair> = keys.iter().map(|key| Keypair::from_private_key(*key)).collect();
+        let coinbases: Vec<Transaction> = keypairs.iter().map(|keypair| Transaction::coinbase(keypair.address(), BALANCE, 0)).collect();
+        let mut accounts = Accounts::new();
+        accounts.apply_block(&coinbases).unwrap();
+        (accounts, keypairs)
+    }
+
+    fn transfer(keypair: &Keypair, amount: u128, fee: u128, nonce: u64) -> Transaction {
+        Transaction::generate_transaction(keypair, RECEIVER, amount, fee, nonce, None)
+    }
+
+    fn hashes(transactions: &[Transaction]) -> Vec<Hash256> {
+        transactions.iter().map(Transaction::get_hash).collect()
+    }
+
+    #[test]
+    fn templates_follow_fee_rate_within_nonce_order() {
+        let (mut accounts, keys) = funded_accounts(&[1, 2]);
+        let mut mempool = Mempool::default();
+        let cheap = transfer(&keys[0], 10, 1, 0);
+        let expensive_follow_up = transfer(&keys[0], 10, 10, 1);
+        let middle = transfer(&keys[1], 10, 5, 0);
+        for transaction in [cheap.clone(), expensive_follow_up.clone(), middle.clone()] {
+            assert!(mempool.insert(transaction, &mut accounts).unwrap().is_empty());
+        }
+
+        let template = mempool.block_template(usize::MAX, usize::MAX);
+        assert_eq!(hashes(&template), hashes(&[middle.clone(), cheap, expensive_follow_up]));
+        assert_eq!(hashes(&mempool.block_template(usize::MAX, 1)), hashes(&[middle]));
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn full_pool_evicts_the_lowest_fee_rate() {
+        let (mut accounts, keys) = funded_accounts(&[1, 2, 3, 4]);
+        let lowest = transfer(&keys[0], 10, 1, 0);
+        let mut mempool = Mempool::new(2 * encoded_size(&lowest));
+        mempool.insert(lowest.clone(), &mut accounts).unwrap();
+        mempool.insert(transfer(&keys[1], 10, 3, 0), &mut accounts).unwrap();
+
+        let dropped = mempool.insert(transfer(&keys[2], 10, 2, 0), &mut accounts).unwrap();
+        assert!(matches!(dropped.as_slice(), [Dropped { hash, reason: DropReason::Evicted }] if *hash == lowest.get_hash()));
+        assert_eq!(mempool.len(), 2);
+        assert!(mempool.size() <= 2 * encoded_size(&lowest));
+        // the evicted reservation is released, so its sender can send the same nonce again
+        accounts.check_transaction(&transfer(&keys[0], BALANCE - 1, 1, 0)).unwrap();
+
+        assert!(mempool.insert(transfer(&keys[3], 10, 1, 0), &mut accounts).is_err());
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn block_drops_pending_transactions_it_invalidates() {
+        let (mut accounts, keys) = funded_accounts(&[1]);
+        let mut mempool = Mempool::default();
+        let pending = [transfer(&keys[0], 30, 1, 0), transfer(&keys[0], 45, 5, 1), transfer(&keys[0], 1, 1, 2)];
+        for transaction in &pending {
+            mempool.insert(transaction.clone(), &mut accounts).unwrap();
+        }
+
+        // a block mined elsewhere spends the first nonce on something else, leaving too little for the second
+        let block = [Transaction::coinbase(RECEIVER, 50, 1), transfer(&keys[0], 50, 1, 0)];
+        accounts.apply_block(&block).unwrap();
+        let dropped = mempool.remove_confirmed(&block, &mut accounts);
+
+        assert!(matches!(
+            dropped.as_slice(),
+            [Dropped { hash: invalid, reason: DropReason::Invalid(_) }, Dropped { hash: gap, reason: DropReason::NonceGap }]
+                if *invalid == pending[1].get_hash() && *gap == pending[2].get_hash()
+        ));
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.size(), 0);
+        assert_eq!(accounts.sign_transaction(&keys[0], RECEIVER, 1, 1, None).get_nonce(), Some(1));
+        mempool.insert(transfer(&keys[0], 48, 1, 1), &mut accounts).unwrap();
+    }
+
+    #[test]
+    fn expired_transactions_take_later_nonces_with_them() {
+        let (mut accounts, keys) = funded_accounts(&[1]);
+        let mut mempool = Mempool::default();
+        let kept = transfer(&keys[0], 30, 1, 0);
+        let expiring = Transaction::generate_transaction(&keys[0], RECEIVER, 40, 1, 1, Some(3));
+        let follow_up = transfer(&keys[0], 20, 1, 2);
+        for transaction in [kept.clone(), expiring.clone(), follow_up.clone()] {
+            mempool.insert(transaction, &mut accounts).unwrap();
+        }
+
+        assert!(mempool.advance_to(3, &mut accounts).is_empty());
+        let dropped = mempool.advance_to(4, &mut accounts);
+        assert!(matches!(
+            dropped.as_slice(),
+            [Dropped { hash: expired, reason: DropReason::Expired }, Dropped { hash: gap, reason: DropReason::NonceGap }]
+                if *expired == expiring.get_hash() && *gap == follow_up.get_hash()
+        ));
+        assert_eq!(hashes(&mempool.block_template(usize::MAX, usize::MAX)), hashes(&[kept]));
+
+        // both reservations are released, so the sender can spend what the dropped transactions held
+        let resend = accounts.sign_transaction(&keys[0], RECEIVER, BALANCE - 32, 1, None);
+        assert_eq!(resend.get_nonce(), Some(1));
+        mempool.insert(resend, &mut accounts).unwrap();
+        assert!(mempool.insert(expiring, &mut accounts).is_err());
+    }
+}
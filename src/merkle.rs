@@ -0,0 +1,189 @@
+use crate::encoding::{Decode, Encode, Reader};
+use crate::error::DecodeError;
+use crate::hash::{Hash256, HASH_LENGTH};
+
+/// Merkle tree over the transaction hashes of a block, keeping every level so inclusion proofs can be built.
+///
+/// Nodes are paired left to right within their own level. When a level has an odd number of nodes the last one is
+/// moved up unchanged instead of being paired with a copy of itself, so repeating the last transaction changes the root.
+/// Leaves and inner nodes are hashed with different tags, so an inner node can not be passed off as a transaction.
+/// The root of an empty tree is zero.
+pub struct MerkleTree {
+    /// Every level from the leaves up to the root.
+    levels: Vec<Vec<Hash256>>,
+}
+
+/// Siblings on the path from a leaf to the merkle root, proving the leaf is in the tree at `index`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct MerkleProof {
+    index: u32,
+    leaf_count: u32,
+    /// Siblings from the leaf up to the root, levels where the node was moved up without a sibling are skipped.
+    siblings: Vec<Hash256>,
+}
+
+impl MerkleTree {
+    /// Builds the tree over the transaction hashes `leaves`, in block order.
+    pub fn new(leaves: Vec<Hash256>) -> Self {
+        let mut levels = vec![leaves.iter().map(Hash256::merkle_leaf).collect::<Vec<Hash256>>()];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let next = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Hash256::merkle_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> Hash256 {
+        self.levels.last().and_then(|level| level.first()).copied().unwrap_or(Hash256::ZERO)
+    }
+
+    /// Proof that the leaf at `index` is in the tree, `None` if there is no such leaf.
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        let leaf_count = self.levels[0].len();
+        if index >= leaf_count {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+        Some(MerkleProof {
+            index: index as u32,
+            leaf_count: leaf_count as u32,
+            siblings,
+        })
+    }
+}
+
+impl MerkleProof {
+    /// Position of the proven leaf, which is the index of the transaction in its block.
+    pub fn get_index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+/// Checks that `proof` links `leaf`, a transaction hash, to the merkle `root` of a block header.
+pub fn verify(root: &Hash256, leaf: &Hash256, proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+
+    let mut node = Hash256::merkle_leaf(leaf);
+    let mut siblings = proof.siblings.iter();
+    let mut position = proof.index;
+    let mut width = proof.leaf_count;
+    while width > 1 {
+        if position % 2 == 1 || position + 1 < width {
+            let Some(sibling) = siblings.next() else {
+                return false;
+            };
+            node = match position % 2 {
+                0 => Hash256::merkle_node(&node, sibling),
+                _ => Hash256::merkle_node(sibling, &node),
+            };
+        }
+        position /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && node == *root
+}
+
+impl Encode for MerkleProof {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.leaf_count.to_be_bytes());
+        bytes.extend_from_slice(&(self.siblings.len() as u32).to_be_bytes());
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling.as_bytes());
+        }
+    }
+}
+
+impl Decode for MerkleProof {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let index = reader.read_u32()?;
+        let leaf_count = reader.read_u32()?;
+        let sibling_count = reader.read_length(HASH_LENGTH)?;
+        let siblings = (0..sibling_count)
+            .map(|_| reader.read_array().map(Hash256::from_bytes))
+            .collect::<Result<Vec<Hash256>, DecodeError>>()?;
+        Ok(MerkleProof { index, leaf_count, siblings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<Hash256> {
+        (0..count).map(|i| Hash256::digest(&[i])).collect()
+    }
+
+    #[test]
+    fn pairs_nodes_within_their_level() {
+        let [a, b, c, d, e] = leaves(5).try_into().unwrap();
+        let leaf = |hash| Hash256::merkle_leaf(&hash);
+        let ab = Hash256::merkle_node(&leaf(a), &leaf(b));
+        let cd = Hash256::merkle_node(&leaf(c), &leaf(d));
+        let root = Hash256::merkle_node(&Hash256::merkle_node(&ab, &cd), &leaf(e));
+
+        assert_eq!(MerkleTree::new(vec![a, b, c, d, e]).root(), root);
+        assert_eq!(MerkleTree::new(vec![a, b, c]).root(), Hash256::merkle_node(&ab, &leaf(c)));
+        assert_eq!(MerkleTree::new(vec![a]).root(), leaf(a));
+        assert_eq!(MerkleTree::new(Vec::new()).root(), Hash256::ZERO);
+        assert_ne!(MerkleTree::new(vec![a, b, c]).root(), MerkleTree::new(vec![a, b, c, c]).root());
+    }
+
+    #[test]
+    fn proves_every_leaf() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let tree = MerkleTree::new(leaves.clone());
+            let root = tree.root();
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.prove(index).unwrap();
+                assert_eq!(proof.get_index(), index);
+                assert!(verify(&root, leaf, &proof));
+                assert!(!verify(&root, &Hash256::digest(b"other"), &proof));
+                assert_eq!(MerkleProof::from_bytes(&proof.to_bytes()).unwrap(), proof);
+            }
+            assert!(tree.prove(leaves.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_proofs_for_another_position() {
+        let leaves = leaves(6);
+        let tree = MerkleTree::new(leaves.clone());
+        let mut proof = tree.prove(2).unwrap();
+        proof.index = 3;
+        assert!(!verify(&tree.root(), &leaves[2], &proof));
+        proof.index = 6;
+        assert!(!verify(&tree.root(), &leaves[2], &proof));
+    }
+
+    #[test]
+    fn inner_nodes_are_not_leaves() {
+        let [a, b, c, d] = leaves(4).try_into().unwrap();
+        let tree = MerkleTree::new(vec![a, b, c, d]);
+        let ab = tree.levels[1][0];
+        assert_ne!(MerkleTree::new(vec![ab, tree.levels[1][1]]).root(), tree.root());
+
+        let proof = MerkleTree::new(vec![ab, c]).prove(0).unwrap();
+        assert!(!verify(&tree.root(), &ab, &proof));
+        let proof = MerkleProof { index: 0, leaf_count: 2, siblings: vec![tree.levels[1][1]] };
+        assert!(!verify(&tree.root(), &ab, &proof));
+    }
+}
@@ -1,9 +1,12 @@
 use crate::blockchain::Chain;
-use crate::sha128::Sha128;
+use crate::error::StorageError;
+use crate::keys::Keypair;
+use crate::storage::{BlockStore, FileStore, MemoryStore};
+use std::path::Path;
 
-pub struct Miner {
-    chain: Chain,
-    private_key: u128,
+pub struct Miner<S: BlockStore = MemoryStore> {
+    chain: Chain<S>,
+    keypair: Keypair,
     threads: u8,
 }
 
@@ -11,29 +14,58 @@ impl Miner {
     pub fn new(private_key: u128, threads: u8) -> Self {
         Miner {
             chain: Chain::init(),
-            private_key,
+            keypair: Keypair::from_private_key(private_key),
             threads,
         }
     }
+}
+
+impl Miner<FileStore> {
+    /// Creates a miner on top of the chain stored in `data_dir`.
+    pub fn open(private_key: u128, threads: u8, data_dir: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Ok(Miner {
+            chain: Chain::open(data_dir)?,
+            keypair: Keypair::from_private_key(private_key),
+            threads,
+        })
+    }
+}
 
+impl<S: BlockStore> Miner<S> {
     pub fn mine(&mut self) {
-        self.chain.mine(Sha128::address(self.private_key), self.threads);
-        println!("Block mined successfully");
-        Miner::print_current(self);
+        match self.chain.mine(self.keypair.address(), self.threads) {
+            Ok(()) => {
+                println!("Block mined successfully");
+                self.print_current();
+            },
+            Err(err) => println!("{}", err),
+        }
     }
 
-    pub fn send_transaction(&mut self, receiver_address: u128, amount: u128) {
-        self.chain.sign_transaction(self.private_key, receiver_address, amount);
+    pub fn send_transaction(&mut self, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) {
+        self.chain.sign_transaction(&self.keypair, receiver_address, amount, fee, expiry);
     }
 
-    pub fn send_transaction_with_another_private_key(&mut self, sender_private_key: u128, receiver_address: u128, amount: u128) {
-        self.chain.sign_transaction(sender_private_key, receiver_address, amount);
+    pub fn send_transaction_with_another_private_key(&mut self, sender_private_key: u128, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) {
+        self.chain.sign_transaction(&Keypair::from_private_key(sender_private_key), receiver_address, amount, fee, expiry);
+    }
+
+    /// Replaces the miner's pending transaction with `nonce` by one paying a higher fee.
+    pub fn replace_transaction(&mut self, nonce: u64, receiver_address: u128, amount: u128, fee: u128, expiry: Option<u64>) {
+        self.chain.replace_transaction(&self.keypair, nonce, receiver_address, amount, fee, expiry);
     }
 
     pub fn print_current(&self) {
         self.chain.print_current();
     }
 
+    pub fn validate_chain(&self) {
+        match self.chain.validate() {
+            Ok(()) => println!("Chain is valid"),
+            Err(err) => println!("{}", err),
+        }
+    }
+
     pub fn print_accounts(&self) {
         self.chain.get_accounts().print_accounts();
     }
@@ -0,0 +1,228 @@
+use crate::encoding::{Decode, Encode, Reader};
+use crate::error::DecodeError;
+use crate::hash::Hash256;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+/// Number of levels below the root of the state tree, one for every bit of an address.
+pub const STATE_TREE_DEPTH: usize = 128;
+
+/// Sparse merkle tree with a leaf for every possible address, committing to the confirmed balance and nonce of every account.
+///
+/// Leaves of accounts that do not exist and subtrees without any accounts hash to `Hash256::ZERO`, so the tree only has to
+/// be built for the existing accounts and the root of an empty tree is zero. The path to a leaf follows the address bits,
+/// most significant bit first.
+///
+/// The roots of subtrees holding at least two accounts are cached, so updating an account only rehashes its own path.
+#[derive(Default)]
+pub struct StateTree {
+    /// Leaf hashes by address.
+    leaves: BTreeMap<u128, Hash256>,
+    /// Root of every subtree with at least two leaves, by level and the address bits above that level.
+    nodes: HashMap<(usize, u128), Hash256>,
+}
+
+/// Hashes of the siblings on the path from an address to the state root, proving either the account at the address
+/// or that there is no account there.
+///
+/// Only siblings of non-empty subtrees are kept, `non_empty` has a bit set for every level where the sibling is not zero.
+#[derive(PartialEq, Clone, Debug)]
+pub struct StateProof {
+    non_empty: u128,
+    /// Non-zero siblings from the leaf up to the root.
+    siblings: Vec<Hash256>,
+}
+
+impl StateTree {
+    /// Builds the tree over `(address, amount, nonce)` for every account.
+    pub fn new(accounts: impl IntoIterator<Item = (u128, u128, u64)>) -> Self {
+        let mut tree = StateTree::default();
+        for (address, amount, nonce) in accounts {
+            tree.update(address, Some((amount, nonce)));
+        }
+        tree
+    }
+
+    pub fn root(&self) -> Hash256 {
+        self.subtree_root(STATE_TREE_DEPTH, 0)
+    }
+
+    /// Sets the leaf of `address` to `account`, its `(amount, nonce)`, or empties it if it is `None`, and rehashes the
+    /// path from the leaf to the root.
+    pub fn update(&mut self, address: u128, account: Option<(u128, u64)>) {
+        let mut node = match account {
+            Some((amount, nonce)) => {
+                let leaf = Hash256::state_leaf(address, amount, nonce);
+                self.leaves.insert(address, leaf);
+                leaf
+            },
+            None => {
+                self.leaves.remove(&address);
+                Hash256::ZERO
+            },
+        };
+        for level in 0..STATE_TREE_DEPTH {
+            let sibling = self.subtree_root(level, prefix(address, level) ^ 1);
+            node = if bit(address, level) { state_node(&sibling, &node) } else { state_node(&node, &sibling) };
+
+            let parent = (level + 1, prefix(address, level + 1));
+            if self.leaf_count(parent.0, parent.1) > 1 {
+                self.nodes.insert(parent, node);
+            } else {
+                self.nodes.remove(&parent);
+            }
+        }
+    }
+
+    /// Proof for the leaf of `address`, whether it has an account or not.
+    pub fn prove(&self, address: u128) -> StateProof {
+        let mut siblings = Vec::new();
+        let mut non_empty = 0;
+        for level in 0..STATE_TREE_DEPTH {
+            let sibling_root = self.subtree_root(level, prefix(address, level) ^ 1);
+            if sibling_root != Hash256::ZERO {
+                non_empty |= 1 << level;
+                siblings.push(sibling_root);
+            }
+        }
+        StateProof { non_empty, siblings }
+    }
+
+    /// Root of the subtree `level` levels above the leaves whose addresses start with the bits `prefix`.
+    fn subtree_root(&self, level: usize, prefix: u128) -> Hash256 {
+        let mut leaves = self.leaves.range(address_range(level, prefix));
+        match (leaves.next(), leaves.next()) {
+            (None, _) => Hash256::ZERO,
+            (Some((address, leaf)), None) => (0..level).fold(*leaf, |node, level| {
+                if bit(*address, level) { state_node(&Hash256::ZERO, &node) } else { state_node(&node, &Hash256::ZERO) }
+            }),
+            (Some(_), Some(_)) => self.nodes[&(level, prefix)],
+        }
+    }
+
+    /// Number of leaves in the subtree, counting no further than two.
+    fn leaf_count(&self, level: usize, prefix: u128) -> usize {
+        self.leaves.range(address_range(level, prefix)).take(2).count()
+    }
+}
+
+impl StateProof {
+    /// Checks that `root` commits to `account`, the `(amount, nonce)` of the account at `address`, or to there being
+    /// no account at `address` if it is `None`.
+    pub fn verify(&self, root: &Hash256, address: u128, account: Option<(u128, u64)>) -> bool {
+        let mut node = account.map_or(Hash256::ZERO, |(amount, nonce)| Hash256::state_leaf(address, amount, nonce));
+        let mut siblings = self.siblings.iter();
+        for level in 0..STATE_TREE_DEPTH {
+            let sibling = match self.non_empty & (1 << level) {
+                0 => Hash256::ZERO,
+                _ => match siblings.next() {
+                    Some(sibling) => *sibling,
+                    None => return false,
+                },
+            };
+            node = if bit(address, level) { state_node(&sibling, &node) } else { state_node(&node, &sibling) };
+        }
+        siblings.next().is_none() && node == *root
+    }
+}
+
+impl Encode for StateProof {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.non_empty.to_be_bytes());
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling.as_bytes());
+        }
+    }
+}
+
+impl Decode for StateProof {
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let non_empty = reader.read_u128()?;
+        let siblings = (0..non_empty.count_ones())
+            .map(|_| reader.read_array().map(Hash256::from_bytes))
+            .collect::<Result<Vec<Hash256>, DecodeError>>()?;
+        Ok(StateProof { non_empty, siblings })
+    }
+}
+
+/// Address bits above `level`, which every address in the same subtree at that level shares.
+fn prefix(address: u128, level: usize) -> u128 {
+    address.checked_shr(level as u32).unwrap_or(0)
+}
+
+/// Addresses in the subtree `level` levels above the leaves whose addresses start with the bits `prefix`.
+fn address_range(level: usize, prefix: u128) -> RangeInclusive<u128> {
+    let first = prefix.checked_shl(level as u32).unwrap_or(0);
+    let last = first | u128::MAX.checked_shr((STATE_TREE_DEPTH - level) as u32).unwrap_or(0);
+    first..=last
+}
+
+fn bit(address: u128, level: usize) -> bool {
+    address & (1 << level) != 0
+}
+
+/// Parent of two nodes, zero if both subtrees are empty.
+fn state_node(left: &Hash256, right: &Hash256) -> Hash256 {
+    if *left == Hash256::ZERO && *right == Hash256::ZERO {
+        return Hash256::ZERO;
+    }
+    Hash256::merkle_node(left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_tree_has_zero_root() {
+        let tree = StateTree::new([]);
+        assert_eq!(tree.root(), Hash256::ZERO);
+        assert!(tree.prove(42).verify(&Hash256::ZERO, 42, None));
+    }
+
+    #[test]
+    fn proves_inclusion_and_non_inclusion() {
+        let accounts = [(0, 5, 0), (1, 7, 2), (u128::MAX, 9, 1), (1 << 100, 3, 4)];
+        let tree = StateTree::new(accounts);
+        let root = tree.root();
+
+        for (address, amount, nonce) in accounts {
+            let proof = tree.prove(address);
+            assert!(proof.verify(&root, address, Some((amount, nonce))));
+            assert!(!proof.verify(&root, address, Some((amount + 1, nonce))));
+            assert!(!proof.verify(&root, address, None));
+            assert_eq!(StateProof::from_bytes(&proof.to_bytes()).unwrap(), proof);
+        }
+        for address in [2, 3, u128::MAX - 1, 1 << 99] {
+            let proof = tree.prove(address);
+            assert!(proof.verify(&root, address, None));
+            assert!(!proof.verify(&root, address, Some((0, 0))));
+        }
+    }
+
+    #[test]
+    fn root_does_not_depend_on_order() {
+        let accounts = [(3, 1, 0), (8, 2, 0), (5, 3, 1)];
+        let mut reversed = accounts;
+        reversed.reverse();
+        assert_eq!(StateTree::new(accounts).root(), StateTree::new(reversed).root());
+        assert_ne!(StateTree::new(accounts).root(), StateTree::new([(3, 1, 0), (8, 2, 0)]).root());
+    }
+
+    #[test]
+    fn updates_match_a_rebuilt_tree() {
+        let mut tree = StateTree::new([(0, 5, 0), (1, 7, 2), (u128::MAX, 9, 1), (1 << 100, 3, 4)]);
+        tree.update(1, Some((6, 3)));
+        tree.update(u128::MAX, None);
+        tree.update(3, Some((1, 0)));
+        let rebuilt = StateTree::new([(0, 5, 0), (1, 6, 3), (1 << 100, 3, 4), (3, 1, 0)]);
+        assert_eq!(tree.root(), rebuilt.root());
+        assert_eq!(tree.prove(u128::MAX), rebuilt.prove(u128::MAX));
+
+        for address in [0, 1, 3, 1 << 100] {
+            tree.update(address, None);
+        }
+        assert_eq!(tree.root(), Hash256::ZERO);
+        assert!(tree.nodes.is_empty());
+    }
+}
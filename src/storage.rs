@@ -0,0 +1,423 @@
+use crate::accounts::BlockUndo;
+use crate::blockchain::Block;
+use crate::encoding::{Decode, Encode};
+use crate::error::StorageError;
+use crate::hash::{Hash256, HASH_LENGTH};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const LOG_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "blocks.idx";
+const UNDO_LOG_FILE: &str = "undo.dat";
+const UNDO_INDEX_FILE: &str = "undo.idx";
+
+/// Every record in the log starts with the payload length and a checksum of the payload.
+const RECORD_HEADER_LENGTH: usize = 4 + HASH_LENGTH;
+const INDEX_ENTRY_LENGTH: usize = 8;
+
+/// Where a `Chain` keeps its blocks. Blocks are addressed by height, starting from genesis at 0.
+///
+/// Next to every block the store keeps the undo data produced by applying it to the accounts, so blocks can be disconnected
+/// without replaying the chain. Undo data is appended after its block, so it can lag behind the blocks after a crash.
+pub trait BlockStore {
+    fn append(&mut self, block: Block) -> Result<(), StorageError>;
+
+    fn get(&self, height: usize) -> Result<Option<Block>, StorageError>;
+
+    fn height_of(&self, hash: &Hash256) -> Option<usize>;
+
+    fn len(&self) -> usize;
+
+    /// Adds the undo data of the block at height `undo_len()`.
+    fn append_undo(&mut self, undo: &BlockUndo) -> Result<(), StorageError>;
+
+    fn get_undo(&self, height: usize) -> Result<Option<BlockUndo>, StorageError>;
+
+    /// Number of blocks that have their undo data stored.
+    fn undo_len(&self) -> usize;
+
+    /// Removes the block at `height` and every block above it together with their undo data,
+    /// used to disconnect blocks when the chain reorganizes.
+    fn truncate(&mut self, height: usize) -> Result<(), StorageError>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_by_hash(&self, hash: &Hash256) -> Result<Option<Block>, StorageError> {
+        match self.height_of(hash) {
+            Some(height) => self.get(height),
+            None => Ok(None),
+        }
+    }
+
+    fn tip(&self) -> Result<Option<Block>, StorageError> {
+        match self.len() {
+            0 => Ok(None),
+            length => self.get(length - 1),
+        }
+    }
+
+    /// Iterates over all blocks from genesis to the tip.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Block, StorageError>> + '_> {
+        Box::new((0..self.len()).map(|height| self.get(height).map(|block| block.expect("height is below the store length"))))
+    }
+}
+
+/// Keeps the blocks in memory only, they are lost when the chain is dropped.
+#[derive(Default)]
+pub struct MemoryStore {
+    blocks: Vec<Block>,
+    undo: Vec<BlockUndo>,
+    heights: HashMap<Hash256, usize>,
+}
+
+impl BlockStore for MemoryStore {
+    fn append(&mut self, block: Block) -> Result<(), StorageError> {
+        self.heights.insert(block.get_hash(), self.blocks.len());
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    fn get(&self, height: usize) -> Result<Option<Block>, StorageError> {
+        Ok(self.blocks.get(height).cloned())
+    }
+
+    fn height_of(&self, hash: &Hash256) -> Option<usize> {
+        self.heights.get(hash).copied()
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn append_undo(&mut self, undo: &BlockUndo) -> Result<(), StorageError> {
+        self.undo.push(undo.clone());
+        Ok(())
+    }
+
+    fn get_undo(&self, height: usize) -> Result<Option<BlockUndo>, StorageError> {
+        Ok(self.undo.get(height).cloned())
+    }
+
+    fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    fn truncate(&mut self, height: usize) -> Result<(), StorageError> {
+        for block in self.blocks.drain(height.min(self.blocks.len())..) {
+            self.heights.remove(&block.get_hash());
+        }
+        self.undo.truncate(height);
+        Ok(())
+    }
+}
+
+/// Append-only block log and undo log, each with an index file holding the offset of every record.
+///
+/// A crash while appending can leave a torn record at the end of a log, it is cut off when the store is opened.
+/// The indexes are only a cache of the logs, so they are rebuilt whenever they do not agree with them.
+pub struct FileStore {
+    blocks: RecordLog,
+    undo: RecordLog,
+    heights: HashMap<Hash256, usize>,
+}
+
+impl FileStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut heights = HashMap::new();
+        let blocks = RecordLog::open(dir, LOG_FILE, INDEX_FILE, |payload| {
+            heights.insert(Block::from_bytes(payload)?.get_hash(), heights.len());
+            Ok(())
+        })?;
+        let mut undo = RecordLog::open(dir, UNDO_LOG_FILE, UNDO_INDEX_FILE, |payload| {
+            BlockUndo::from_bytes(payload)?;
+            Ok(())
+        })?;
+
+        // undo data is written after its block and cut off before it, so this is not expected, but keep the logs aligned
+        if undo.len() > blocks.len() {
+            undo.truncate(blocks.len())?;
+        }
+
+        Ok(FileStore {
+            blocks,
+            undo,
+            heights,
+        })
+    }
+}
+
+impl BlockStore for FileStore {
+    fn append(&mut self, block: Block) -> Result<(), StorageError> {
+        self.blocks.append(&block.to_bytes())?;
+        self.heights.insert(block.get_hash(), self.blocks.len() - 1);
+        Ok(())
+    }
+
+    fn get(&self, height: usize) -> Result<Option<Block>, StorageError> {
+        match self.blocks.read(height)? {
+            Some(payload) => Ok(Some(Block::from_bytes(&payload)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn height_of(&self, hash: &Hash256) -> Option<usize> {
+        self.heights.get(hash).copied()
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn append_undo(&mut self, undo: &BlockUndo) -> Result<(), StorageError> {
+        self.undo.append(&undo.to_bytes())
+    }
+
+    fn get_undo(&self, height: usize) -> Result<Option<BlockUndo>, StorageError> {
+        match self.undo.read(height)? {
+            Some(payload) => Ok(Some(BlockUndo::from_bytes(&payload)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Cuts the logs and the indexes back to the records at `height`.
+    fn truncate(&mut self, height: usize) -> Result<(), StorageError> {
+        self.undo.truncate(height)?;
+        self.blocks.truncate(height)?;
+        self.heights.retain(|_, block_height| *block_height < height);
+        Ok(())
+    }
+}
+
+/// Log file of checksummed records with an index file holding the offset of every record.
+struct RecordLog {
+    log: File,
+    index: File,
+    offsets: Vec<u64>,
+    log_length: u64,
+}
+
+impl RecordLog {
+    /// Opens the log, cutting off a torn record at its end and rebuilding the index if it does not match.
+    /// `check` is called with the payload of every complete record in order.
+    ///
+    /// A record is only indexed once it is fully written, so a broken record the index lists records after is not a torn
+    /// write but damage in the middle of the log. The log is left as it is in that case instead of losing every record after it.
+    fn open(dir: &Path, log_file: &str, index_file: &str, check: impl FnMut(&[u8]) -> Result<(), StorageError>) -> Result<Self, StorageError> {
+        let mut log = OpenOptions::new().read(true).append(true).create(true).open(dir.join(log_file))?;
+        let mut log_bytes = Vec::new();
+        log.read_to_end(&mut log_bytes)?;
+
+        let mut index = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(index_file))?;
+        let mut index_bytes = Vec::new();
+        index.read_to_end(&mut index_bytes)?;
+        let indexed_offsets = index_bytes
+            .chunks_exact(INDEX_ENTRY_LENGTH)
+            .map(|entry| u64::from_be_bytes(entry.try_into().unwrap()))
+            .collect::<Vec<u64>>();
+
+        let LogScan { offsets, log_length } = scan_log(&log_bytes, check)?;
+        if log_length < log_bytes.len() as u64 {
+            if indexed_offsets.iter().any(|offset| *offset >= log_length) {
+                return Err(StorageError::Corrupt(format!("broken record at offset {log_length} is followed by indexed records")));
+            }
+            log.set_len(log_length)?;
+            log.sync_all()?;
+        }
+
+        if indexed_offsets != offsets || index_bytes.len() % INDEX_ENTRY_LENGTH != 0 {
+            index.set_len(0)?;
+            index.seek(SeekFrom::Start(0))?;
+            for offset in &offsets {
+                index.write_all(&offset.to_be_bytes())?;
+            }
+            index.sync_all()?;
+        }
+
+        Ok(RecordLog {
+            log,
+            index,
+            offsets,
+            log_length,
+        })
+    }
+
+    fn append(&mut self, payload: &[u8]) -> Result<(), StorageError> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_LENGTH + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(Hash256::digest(payload).as_bytes());
+        record.extend_from_slice(payload);
+
+        if let Err(err) = self.write_record(&record) {
+            // cut off whatever part of the record made it to disk, so the next append starts at `log_length` again
+            self.log.set_len(self.log_length)?;
+            self.index.set_len((self.offsets.len() * INDEX_ENTRY_LENGTH) as u64)?;
+            return Err(err);
+        }
+
+        self.offsets.push(self.log_length);
+        self.log_length += record.len() as u64;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> Result<(), StorageError> {
+        self.log.write_all(record)?;
+        self.log.sync_data()?;
+
+        self.index.seek(SeekFrom::End(0))?;
+        self.index.write_all(&self.log_length.to_be_bytes())?;
+        self.index.sync_data()?;
+        Ok(())
+    }
+
+    fn read(&self, position: usize) -> Result<Option<Vec<u8>>, StorageError> {
+        let Some(&offset) = self.offsets.get(position) else {
+            return Ok(None);
+        };
+
+        let mut log = &self.log;
+        let mut record_header = [0u8; RECORD_HEADER_LENGTH];
+        log.seek(SeekFrom::Start(offset))?;
+        log.read_exact(&mut record_header)?;
+        let mut payload = vec![0u8; u32::from_be_bytes(record_header[..4].try_into().unwrap()) as usize];
+        log.read_exact(&mut payload)?;
+        Ok(Some(payload))
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn truncate(&mut self, length: usize) -> Result<(), StorageError> {
+        let Some(&offset) = self.offsets.get(length) else {
+            return Ok(());
+        };
+
+        self.log.set_len(offset)?;
+        self.log.sync_data()?;
+        self.index.set_len((length * INDEX_ENTRY_LENGTH) as u64)?;
+        self.index.sync_data()?;
+
+        self.offsets.truncate(length);
+        self.log_length = offset;
+        Ok(())
+    }
+}
+
+/// The complete records found in a log and the length of the valid part of it.
+struct LogScan {
+    offsets: Vec<u64>,
+    log_length: u64,
+}
+
+fn scan_log(log_bytes: &[u8], mut check: impl FnMut(&[u8]) -> Result<(), StorageError>) -> Result<LogScan, StorageError> {
+    let mut offsets = Vec::new();
+    let mut position = 0;
+
+    while position < log_bytes.len() {
+        let rest = &log_bytes[position..];
+        if rest.len() < RECORD_HEADER_LENGTH {
+            break;
+        }
+
+        let payload_length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let checksum = Hash256::from_bytes(rest[4..RECORD_HEADER_LENGTH].try_into().unwrap());
+        let record_end = RECORD_HEADER_LENGTH + payload_length;
+        if rest.len() < record_end {
+            break;
+        }
+
+        let payload = &rest[RECORD_HEADER_LENGTH..record_end];
+        if Hash256::digest(payload) != checksum {
+            // a bad checksum on the last record is a torn write, anywhere else the log is damaged
+            if rest.len() == record_end {
+                break;
+            }
+            return Err(StorageError::Corrupt(format!("bad checksum for record at offset {position}")));
+        }
+        check(payload)?;
+        offsets.push(position as u64);
+        position += record_end;
+    }
+
+    Ok(LogScan {
+        offsets,
+        log_length: position as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Chain;
+    use crate::test_utils::{mine_blocks, test_params};
+
+    /// Empty directory for a test, removed again when dropped.
+    struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rustychain-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn stored_chain(dir: &Path, blocks: usize) -> u64 {
+        let mut chain = Chain::with_params(FileStore::open(dir).unwrap(), test_params()).unwrap();
+        mine_blocks(&mut chain, blocks);
+        fs::metadata(dir.join(LOG_FILE)).unwrap().len()
+    }
+
+    #[test]
+    fn cuts_off_a_torn_record_at_the_end() {
+        let dir = TestDir::new("torn-tail");
+        let log_length = stored_chain(&dir.0, 2);
+
+        // header of a record claiming more bytes than were written before the crash
+        let mut log = OpenOptions::new().append(true).open(dir.0.join(LOG_FILE)).unwrap();
+        log.write_all(&1000u32.to_be_bytes()).unwrap();
+        log.write_all(&[7; HASH_LENGTH + 10]).unwrap();
+        drop(log);
+
+        let mut chain = Chain::with_params(FileStore::open(&dir.0).unwrap(), test_params()).unwrap();
+        assert_eq!(chain.get_store().len(), 3);
+        assert_eq!(fs::metadata(dir.0.join(LOG_FILE)).unwrap().len(), log_length);
+
+        mine_blocks(&mut chain, 1);
+        drop(chain);
+        let chain = Chain::with_params(FileStore::open(&dir.0).unwrap(), test_params()).unwrap();
+        assert_eq!(chain.get_store().len(), 4);
+        chain.validate().unwrap();
+    }
+
+    #[test]
+    fn refuses_to_cut_off_indexed_records_after_a_broken_length() {
+        let dir = TestDir::new("broken-length");
+        let log_length = stored_chain(&dir.0, 2);
+
+        let second_record = u64::from_be_bytes(fs::read(dir.0.join(INDEX_FILE)).unwrap()[INDEX_ENTRY_LENGTH..2 * INDEX_ENTRY_LENGTH].try_into().unwrap());
+        let mut log_bytes = fs::read(dir.0.join(LOG_FILE)).unwrap();
+        log_bytes[second_record as usize..second_record as usize + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        fs::write(dir.0.join(LOG_FILE), &log_bytes).unwrap();
+
+        assert!(matches!(FileStore::open(&dir.0), Err(StorageError::Corrupt(_))));
+        assert_eq!(fs::metadata(dir.0.join(LOG_FILE)).unwrap().len(), log_length);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::hash::Hash256;
+use std::fmt;
+
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod u256 {
+    uint::construct_uint! {
+        /// 256-bit unsigned integer used for proof of work targets and chain work.
+        pub struct U256(4);
+    }
+}
+
+pub use u256::U256;
+
+const MANTISSA_MASK: u32 = 0x007f_ffff;
+const SIGN_BIT: u32 = 0x0080_0000;
+
+/// Proof of work target in compact form, like Bitcoin's `nBits`.
+///
+/// The top byte is a base 256 exponent and the low three bytes are the mantissa,
+/// so the target is `mantissa * 256^(exponent - 3)`. A block hash meets the target when it is less than or equal to it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct CompactTarget(u32);
+
+impl CompactTarget {
+    pub fn from_bits(bits: u32) -> Self {
+        CompactTarget(bits)
+    }
+
+    pub fn to_bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Compact form of the largest target with `zeros` leading zero bits.
+    pub fn from_leading_zeros(zeros: u8) -> Self {
+        CompactTarget::from_target(U256::MAX >> zeros as usize)
+    }
+
+    /// Encodes a target, rounding it down to the precision of the mantissa.
+    pub fn from_target(target: U256) -> Self {
+        let mut size = (target.bits() as u32).div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            target.low_u32() << (8 * (3 - size))
+        } else {
+            (target >> (8 * (size - 3)) as usize).low_u32()
+        };
+
+        // the mantissa is signed in Bitcoin's format, keep it positive by moving a byte into the exponent
+        if mantissa & SIGN_BIT != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        CompactTarget(mantissa | size << 24)
+    }
+
+    /// Expands the target. Negative or overflowing targets are `None`.
+    pub fn to_target(&self) -> Option<U256> {
+        let size = self.0 >> 24;
+        let mantissa = self.0 & MANTISSA_MASK;
+        if self.0 & SIGN_BIT != 0 && mantissa != 0 {
+            return None;
+        }
+
+        if size <= 3 {
+            Some(U256::from(mantissa >> (8 * (3 - size))))
+        } else {
+            let shift = 8 * (size - 3);
+            if mantissa != 0 && shift + 32 - mantissa.leading_zeros() > 256 {
+                return None;
+            }
+            Some(U256::from(mantissa) << shift as usize)
+        }
+    }
+
+    pub fn is_met_by(&self, hash: &Hash256) -> bool {
+        match self.to_target() {
+            Some(target) => U256::from_big_endian(hash.as_bytes()) <= target,
+            None => false,
+        }
+    }
+
+    /// Expected number of hashes needed to meet the target, `2^256 / (target + 1)`.
+    pub fn work(&self) -> U256 {
+        match self.to_target() {
+            Some(target) if target != U256::MAX => (!target / (target + 1)).saturating_add(U256::one()),
+            Some(_) => U256::one(),
+            None => U256::zero(),
+        }
+    }
+}
+
+impl fmt::Display for CompactTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(value: U256) -> Hash256 {
+        let mut bytes = [0u8; 32];
+        value.to_big_endian(&mut bytes);
+        Hash256::from_bytes(bytes)
+    }
+
+    #[test]
+    fn round_trips_compact_targets() {
+        for bits in [0x1d00ffff, 0x2000ffff, 0x03123456, 0x01340000, 0x207fffff] {
+            let target = CompactTarget::from_bits(bits).to_target().unwrap();
+            assert_eq!(CompactTarget::from_target(target), CompactTarget::from_bits(bits));
+        }
+        assert_eq!(CompactTarget::from_bits(0x1d00ffff).to_target(), Some(U256::from(0xffff) << 208));
+        assert_eq!(CompactTarget::from_target(U256::zero()).to_target(), Some(U256::zero()));
+    }
+
+    #[test]
+    fn moves_a_signed_mantissa_byte_into_the_exponent() {
+        for target in [U256::from(0x80), U256::from(0x80_0000), U256::from(0x80) << 200] {
+            let compact = CompactTarget::from_target(target);
+            assert_eq!(compact.to_bits() & SIGN_BIT, 0);
+            assert_eq!(compact.to_target(), Some(target));
+        }
+        assert_eq!(CompactTarget::from_target(U256::from(0x80)).to_bits(), 0x02008000);
+    }
+
+    #[test]
+    fn rejects_negative_and_overflowing_targets() {
+        assert_eq!(CompactTarget::from_bits(0x04923456).to_target(), None);
+        assert_eq!(CompactTarget::from_bits(0x01fedcba).to_target(), None);
+        assert_eq!(CompactTarget::from_bits(0x04800000).to_target(), Some(U256::zero()));
+        assert_eq!(CompactTarget::from_bits(0x2100ffff).to_target(), Some(U256::from(0xffff) << 240));
+        assert_eq!(CompactTarget::from_bits(0x21010000).to_target(), None);
+        assert_eq!(CompactTarget::from_bits(0xff123456).to_target(), None);
+        assert!(!CompactTarget::from_bits(0x04923456).is_met_by(&Hash256::ZERO));
+    }
+
+    #[test]
+    fn target_is_met_up_to_and_including_itself() {
+        let compact = CompactTarget::from_bits(0x2000ffff);
+        let target = compact.to_target().unwrap();
+        assert!(compact.is_met_by(&Hash256::ZERO));
+        assert!(compact.is_met_by(&hash_of(target)));
+        assert!(!compact.is_met_by(&hash_of(target + 1)));
+        assert!(!CompactTarget::from_target(U256::zero()).is_met_by(&hash_of(U256::one())));
+    }
+
+    #[test]
+    fn work_is_the_expected_number_of_hashes() {
+        assert_eq!(CompactTarget::from_bits(0x2000ffff).work(), U256::from(256));
+        assert_eq!(CompactTarget::from_leading_zeros(4).work(), U256::from(16));
+        // a zero target needs 2^256 hashes, which saturates
+        assert_eq!(CompactTarget::from_target(U256::zero()).work(), U256::MAX);
+        // the easiest encodable target is just below `U256::MAX`
+        assert_eq!(CompactTarget::from_target(U256::MAX).work(), U256::one());
+        assert_eq!(CompactTarget::from_bits(0x04923456).work(), U256::zero());
+    }
+}
@@ -0,0 +1,33 @@
+use crate::blockchain::Chain;
+use crate::consensus::ConsensusParams;
+use crate::keys::Keypair;
+use crate::storage::{BlockStore, MemoryStore};
+use crate::target::CompactTarget;
+
+/// Private key of the account the test chains are mined by.
+pub(crate) const MINER_KEY: u128 = 7;
+/// Address of an account that only receives coins.
+pub(crate) const RECEIVER: u128 = 5;
+
+/// Default rules with a target easy enough to mine a block instantly.
+pub(crate) fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        initial_target: CompactTarget::from_leading_zeros(4),
+        ..ConsensusParams::default()
+    }
+}
+
+/// In-memory chain with one block mined by `MINER_KEY` on top of genesis.
+pub(crate) fn test_chain() -> Chain {
+    let mut chain = Chain::with_params(MemoryStore::default(), test_params()).unwrap();
+    mine_blocks(&mut chain, 1);
+    chain
+}
+
+/// Mines `count` blocks paying `MINER_KEY`.
+pub(crate) fn mine_blocks<S: BlockStore>(chain: &mut Chain<S>, count: usize) {
+    let miner = Keypair::from_private_key(MINER_KEY);
+    for _ in 0..count {
+        chain.mine(miner.address(), 1).unwrap();
+    }
+}